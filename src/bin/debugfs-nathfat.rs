@@ -0,0 +1,667 @@
+use std::{
+    collections::HashSet,
+    env,
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, Read, Seek, Write},
+    process::exit,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use naths_fat_fs::{
+    consts::{DIR_ENTRY_SIZE, DNA, EOC, FAT_START_ADDR, FRE, INODE_NAME_SIZE},
+    fs::{
+        basic_fs_io::{BaseIO, FileSystemBasicIO},
+        directory::{DirectoryEntry, Inode},
+        volume::Volume,
+    },
+    utility::fs_utility::{from_inode, get_data_section_address, to_inode},
+    DirEntry, FatEntry,
+};
+
+const USAGE: &str = "usage: debugfs-nathfat [-w] [-R REQUEST] IMAGE";
+
+const HELP: &str = "commands:
+  header                              show the header and free space
+  fat CLUSTER [COUNT]                 print FAT entries
+  chain CLUSTER                       follow a chain from CLUSTER
+  hexdump CLUSTER                     hex dump a cluster
+  dir CLUSTER                         decode every directory entry in a cluster
+  entry CLUSTER IDX                   decode one directory entry and show its raw bytes
+  journal                             show the transaction waiting in the journal
+  lookup PATH                         location and inode number of PATH
+  inode INO                           location of an inode number and its entry
+  ino CLUSTER IDX                     inode number of a location
+  set_fat CLUSTER VALUE [-f]          write a FAT entry (VALUE may be eoc, free or dna)
+  set_entry CLUSTER IDX FIELD VALUE   change name, length, uid, gid, mode, start, hlinks,
+                                      atime, mtime or ctime of an entry
+  clear_entry CLUSTER IDX             mark a directory entry as unused
+  help
+  quit
+
+numbers may be given in decimal or as 0x hex; set_* and clear_* need -w
+
+without -w a transaction left in the journal isn't replayed; journal lists it
+and the other commands show the image as replaying it would leave it";
+
+type Image = Volume<FileSystemBasicIO<File>>;
+
+struct Debugfs {
+    volume: Image,
+    writable: bool,
+}
+
+fn parse<T: TryFrom<u64>>(value: &str) -> Result<T, Box<dyn Error>> {
+    let number = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+
+    number
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("invalid number: {}", value).into())
+}
+
+fn fat_value(entry: FatEntry) -> String {
+    match entry {
+        EOC => String::from("EOC"),
+        DNA => String::from("DNA"),
+        FRE => String::from("free"),
+        next => format!("{:#010X}", next),
+    }
+}
+
+fn time(time: SystemTime) -> String {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    format!("{}.{:03}", millis / 1000, millis % 1000)
+}
+
+fn flags(raw: &DirEntry) -> String {
+    let mut names = vec![];
+
+    if raw[0] & 0b001 == 0 {
+        names.push("unused");
+    } else {
+        names.push("valid");
+
+        if raw[0] & 0b010 != 0 {
+            names.push("long name");
+        } else if raw[0] & 0b100 != 0 {
+            names.push("directory");
+        } else {
+            names.push("file");
+        }
+    }
+
+    format!("flags={:#06b} [{}]", raw[0] & 0x0F, names.join(", "))
+}
+
+fn describe(raw: &DirEntry) -> String {
+    let entry = match DirectoryEntry::try_from(raw) {
+        Ok(entry) => entry,
+        Err(e) => return format!("{} <{}>", flags(raw), e),
+    };
+
+    match entry {
+        DirectoryEntry::Invalid => flags(raw),
+        DirectoryEntry::LongFileName(name) => format!("{} {:?}", flags(raw), name),
+        DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) => format!(
+            "{} hlinks={} {:?} start={} length={} mode={:04o} uid={} gid={} \
+             atime={} mtime={} ctime={}",
+            flags(raw),
+            inode.number_of_hlinks,
+            inode.name,
+            fat_value(inode.start_cluster),
+            inode.length,
+            inode.permission,
+            inode.uid,
+            inode.gid,
+            time(inode.atime),
+            time(inode.mtime),
+            time(inode.ctime)
+        ),
+    }
+}
+
+fn hexdump(data: &[u8], base: u64) {
+    let mut previous: Option<&[u8]> = None;
+    let mut skipping = false;
+
+    for (n, line) in data.chunks(16).enumerate() {
+        if previous == Some(line) {
+            if !skipping {
+                println!("*");
+                skipping = true;
+            }
+
+            continue;
+        }
+
+        previous = Some(line);
+        skipping = false;
+
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|b| match b {
+                0x20..=0x7E => *b as char,
+                _ => '.',
+            })
+            .collect();
+
+        println!(
+            "{:08x}  {:<47}  |{}|",
+            base + n as u64 * 16,
+            hex.join(" "),
+            ascii
+        );
+    }
+
+    println!("{:08x}", base + data.len() as u64);
+}
+
+impl Debugfs {
+    fn io(&mut self) -> &mut FileSystemBasicIO<File> {
+        &mut self.volume.fs.io
+    }
+
+    fn entries_per_cluster(&self) -> u32 {
+        self.volume.fs.io.entries_per_cluster()
+    }
+
+    fn address(&self, cluster: FatEntry) -> u64 {
+        let io = &self.volume.fs.io;
+
+        io.start_data_region + (cluster - 1) as u64 * io.cluster_size() as u64
+    }
+
+    fn check_cluster(&self, cluster: FatEntry) -> Result<(), Box<dyn Error>> {
+        if cluster == 0 || cluster > self.volume.fs.io.fat_length() {
+            return Err(format!("cluster {:#010X} is out of range", cluster).into());
+        }
+
+        Ok(())
+    }
+
+    fn check_location(&self, cluster: FatEntry, idx: u32) -> Result<(), Box<dyn Error>> {
+        self.check_cluster(cluster)?;
+
+        if idx >= self.entries_per_cluster() {
+            return Err(format!("index {} is out of range", idx).into());
+        }
+
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), Box<dyn Error>> {
+        if !self.writable {
+            return Err("image is opened read-only, use -w".into());
+        }
+
+        Ok(())
+    }
+
+    fn header(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut raw = [0u8; FAT_START_ADDR as usize];
+
+        let device = &mut self.io().device;
+        device.rewind()?;
+        device.read_exact(&mut raw)?;
+
+        let fat_length = self.volume.fs.io.fat_length();
+        let cluster_size = self.volume.fs.io.cluster_size();
+
+        hexdump(&raw, 0);
+        println!("magic:           {}", String::from_utf8_lossy(&raw[0..9]));
+        println!("version:         {}", raw[9]);
+        println!("clusters:        {}", fat_length);
+        println!("cluster size:    {}", cluster_size);
+        println!("entries/cluster: {}", self.entries_per_cluster());
+        println!("FAT:             {:#010X}", FAT_START_ADDR);
+        println!(
+            "data region:     {:#010X}",
+            get_data_section_address(fat_length)
+        );
+        match self.volume.fs.io.journal() {
+            Some((start, length)) => println!(
+                "journal:         {:#010X} - {:#010X} ({} clusters)",
+                start,
+                start + length - 1,
+                length
+            ),
+            None => println!("journal:         none"),
+        }
+        println!("free clusters:   {}", self.volume.fs.free_clusters()?);
+
+        Ok(())
+    }
+
+    fn fat(&mut self, cluster: FatEntry, count: u32) -> Result<(), Box<dyn Error>> {
+        self.check_cluster(cluster)?;
+
+        let count = count.min(self.volume.fs.io.fat_length() - cluster + 1);
+
+        for (n, entry) in self.io().read_fat(cluster, count)?.iter().enumerate() {
+            println!("{:#010X}: {}", cluster + n as u32, fat_value(*entry));
+        }
+
+        Ok(())
+    }
+
+    fn chain(&mut self, cluster: FatEntry) -> Result<(), Box<dyn Error>> {
+        self.check_cluster(cluster)?;
+
+        let mut chain = vec![cluster];
+        let mut seen = HashSet::from([cluster]);
+
+        // unlike get_chain this shows where a broken chain goes wrong
+        let end = loop {
+            let next = self.io().read_fat_entry(chain[chain.len() - 1])?;
+
+            if next == EOC {
+                break String::from("EOC");
+            } else if self.check_cluster(next).is_err() || next == DNA {
+                break format!("{} (bad link)", fat_value(next));
+            } else if !seen.insert(next) {
+                break format!("{:#010X} (loop)", next);
+            }
+
+            chain.push(next);
+        };
+
+        let links: Vec<String> = chain.iter().map(|c| format!("{:#010X}", c)).collect();
+
+        println!("{} -> {}", links.join(" -> "), end);
+        println!("{} clusters", chain.len());
+
+        Ok(())
+    }
+
+    fn dump(&mut self, cluster: FatEntry) -> Result<(), Box<dyn Error>> {
+        self.check_cluster(cluster)?;
+
+        let data = self.io().read_cluster(cluster)?;
+
+        hexdump(&data, self.address(cluster));
+
+        Ok(())
+    }
+
+    fn dir(&mut self, cluster: FatEntry) -> Result<(), Box<dyn Error>> {
+        self.check_cluster(cluster)?;
+
+        for idx in 0..self.entries_per_cluster() {
+            let raw = self.io().read_raw_directory_entry(cluster, idx)?;
+
+            if raw[0] & 1 != 0 {
+                println!(
+                    "{:4} ino={:<8} {}",
+                    idx,
+                    self.ino(cluster, idx),
+                    describe(&raw)
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn entry(&mut self, cluster: FatEntry, idx: u32) -> Result<(), Box<dyn Error>> {
+        self.check_location(cluster, idx)?;
+
+        let raw = self.io().read_raw_directory_entry(cluster, idx)?;
+
+        println!("ino={} {}", self.ino(cluster, idx), describe(&raw));
+        hexdump(&raw, self.address(cluster) + (idx * DIR_ENTRY_SIZE) as u64);
+
+        Ok(())
+    }
+
+    fn journal(&mut self) -> Result<(), Box<dyn Error>> {
+        let transaction = match (self.io().journal(), &self.io().pending) {
+            (None, _) => return Err("the image has no journal".into()),
+            (Some(_), None) => {
+                println!("no transaction pending");
+                return Ok(());
+            }
+            (Some(_), Some(transaction)) => transaction,
+        };
+
+        for (cluster, entry) in &transaction.fat {
+            println!("fat   {:#010X}: {}", cluster, fat_value(*entry));
+        }
+
+        for ((cluster, idx), raw) in &transaction.entries {
+            println!("entry {:#010X} {:4}: {}", cluster, idx, describe(raw));
+        }
+
+        Ok(())
+    }
+
+    fn ino(&self, cluster: FatEntry, idx: u32) -> u64 {
+        to_inode(cluster, idx, self.entries_per_cluster())
+    }
+
+    fn lookup(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let (cluster, idx) = self.volume.lookup(path)?;
+
+        println!(
+            "{}: cluster={:#010X} index={} ino={}",
+            path,
+            cluster,
+            idx,
+            self.ino(cluster, idx)
+        );
+
+        Ok(())
+    }
+
+    fn inode(&mut self, ino: u64) -> Result<(), Box<dyn Error>> {
+        if ino == 0 {
+            return Err("inode numbers start at 1".into());
+        }
+
+        let (cluster, idx) = from_inode(ino, self.entries_per_cluster());
+
+        println!("cluster={:#010X} index={}", cluster, idx);
+
+        self.entry(cluster, idx)
+    }
+
+    fn set_fat(
+        &mut self,
+        cluster: FatEntry,
+        value: &str,
+        force: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_writable()?;
+        self.check_cluster(cluster)?;
+
+        let value = match value {
+            "eoc" | "EOC" => EOC,
+            "dna" | "DNA" => DNA,
+            "free" | "FRE" => FRE,
+            value => parse(value)?,
+        };
+
+        if ![EOC, DNA, FRE].contains(&value) && !force {
+            self.check_cluster(value)
+                .map_err(|e| format!("{}, use -f to write it anyway", e))?;
+        }
+
+        let old = self.io().read_fat_entry(cluster)?;
+        self.volume.fs.write_fat_entry(cluster, value)?;
+
+        println!(
+            "{:#010X}: {} -> {}",
+            cluster,
+            fat_value(old),
+            fat_value(value)
+        );
+
+        Ok(())
+    }
+
+    fn set_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+        field: &str,
+        value: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_writable()?;
+        self.check_location(cluster, idx)?;
+
+        let mut entry = self.volume.fs.read_directory_entry(cluster, idx)?;
+
+        let inode: &mut Inode = match &mut entry {
+            DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) => inode,
+            _ => return Err("not a file or directory entry".into()),
+        };
+
+        let seconds = |value| -> Result<SystemTime, Box<dyn Error>> {
+            Ok(UNIX_EPOCH + Duration::from_secs(parse(value)?))
+        };
+
+        match field {
+            "name" => {
+                if value.len() > INODE_NAME_SIZE as usize {
+                    return Err(format!(
+                        "names stored in the entry itself are at most {} bytes",
+                        INODE_NAME_SIZE
+                    )
+                    .into());
+                }
+
+                inode.name = value.to_string();
+            }
+            "length" => inode.length = parse(value)?,
+            "uid" => inode.uid = parse(value)?,
+            "gid" => inode.gid = parse(value)?,
+            "mode" => {
+                inode.permission = u16::from_str_radix(value, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o7777)
+                    .ok_or(format!("invalid mode: {}", value))?
+            }
+            "start" => {
+                let start = parse(value)?;
+                self.check_cluster(start)?;
+                inode.start_cluster = start;
+            }
+            "hlinks" => {
+                inode.number_of_hlinks = parse(value)?;
+
+                if inode.number_of_hlinks > 0x0F {
+                    return Err("hlinks is stored in 4 bits".into());
+                }
+            }
+            "atime" => inode.atime = seconds(value)?,
+            "mtime" => inode.mtime = seconds(value)?,
+            "ctime" => inode.ctime = seconds(value)?,
+            _ => return Err(format!("unknown field: {}", field).into()),
+        }
+
+        self.volume.fs.write_directory_entry(cluster, idx, &entry)?;
+
+        // a long name in front of the entry would still be prepended to the new one
+        if field == "name" {
+            self.clear_long_name(cluster, idx)?;
+        }
+
+        self.entry(cluster, idx)
+    }
+
+    /// Location of the entry in front of `cluster`/`idx`, following the FAT
+    /// backwards at the start of a cluster.
+    fn previous_location(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+    ) -> Result<Option<(FatEntry, u32)>, Box<dyn Error>> {
+        if idx > 0 {
+            return Ok(Some((cluster, idx - 1)));
+        }
+
+        let fat_length = self.volume.fs.io.fat_length();
+        let previous = self
+            .io()
+            .read_fat(1, fat_length)?
+            .iter()
+            .position(|entry| *entry == cluster);
+
+        Ok(previous.map(|p| (p as FatEntry + 1, self.entries_per_cluster() - 1)))
+    }
+
+    fn clear_long_name(&mut self, cluster: FatEntry, idx: u32) -> Result<(), Box<dyn Error>> {
+        let mut location = self.previous_location(cluster, idx)?;
+        let mut cleared = 0;
+
+        while let Some((c, i)) = location {
+            if !matches!(
+                self.volume.fs.read_directory_entry(c, i)?,
+                DirectoryEntry::LongFileName(_)
+            ) || cleared > self.volume.fs.io.fat_length() * self.entries_per_cluster()
+            {
+                break;
+            }
+
+            self.volume
+                .fs
+                .write_directory_entry(c, i, &DirectoryEntry::Invalid)?;
+
+            cleared += 1;
+            location = self.previous_location(c, i)?;
+        }
+
+        if cleared > 0 {
+            println!("cleared {} long name entries", cleared);
+        }
+
+        Ok(())
+    }
+
+    fn clear_entry(&mut self, cluster: FatEntry, idx: u32) -> Result<(), Box<dyn Error>> {
+        self.check_writable()?;
+        self.check_location(cluster, idx)?;
+
+        self.volume
+            .fs
+            .write_directory_entry(cluster, idx, &DirectoryEntry::Invalid)?;
+
+        Ok(())
+    }
+
+    /// Runs one request, returns false on `quit`.
+    fn execute(&mut self, line: &str) -> Result<bool, Box<dyn Error>> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+
+        match args.as_slice() {
+            [] => (),
+            ["quit" | "q" | "exit"] => return Ok(false),
+            ["help" | "?"] => println!("{}", HELP),
+            ["header" | "stats"] => self.header()?,
+            ["fat", cluster] => self.fat(parse(cluster)?, 1)?,
+            ["fat", cluster, count] => self.fat(parse(cluster)?, parse(count)?)?,
+            ["chain", cluster] => self.chain(parse(cluster)?)?,
+            ["hexdump" | "dump", cluster] => self.dump(parse(cluster)?)?,
+            ["dir", cluster] => self.dir(parse(cluster)?)?,
+            ["entry", cluster, idx] => self.entry(parse(cluster)?, parse(idx)?)?,
+            ["journal"] => self.journal()?,
+            ["lookup", path] => self.lookup(path)?,
+            ["inode", ino] => self.inode(parse(ino)?)?,
+            ["ino", cluster, idx] => {
+                let (cluster, idx) = (parse(cluster)?, parse(idx)?);
+                self.check_location(cluster, idx)?;
+                println!("{}", self.ino(cluster, idx));
+            }
+            ["set_fat", cluster, value] => self.set_fat(parse(cluster)?, value, false)?,
+            ["set_fat", cluster, value, "-f"] => self.set_fat(parse(cluster)?, value, true)?,
+            ["set_entry", cluster, idx, field, value @ ..] if !value.is_empty() => {
+                self.set_entry(parse(cluster)?, parse(idx)?, field, &value.join(" "))?
+            }
+            ["clear_entry", cluster, idx] => self.clear_entry(parse(cluster)?, parse(idx)?)?,
+            [command, ..] => return Err(format!("{}: bad usage, see help", command).into()),
+        }
+
+        Ok(true)
+    }
+}
+
+fn run() -> Result<i32, Box<dyn Error>> {
+    let mut writable = false;
+    let mut request = None;
+    let mut image = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-w" => writable = true,
+            "-R" => request = Some(args.next().ok_or(USAGE)?),
+            "-h" | "--help" => {
+                println!("{}\n\n{}", USAGE, HELP);
+                return Ok(0);
+            }
+            _ if image.is_none() && !arg.starts_with('-') => image = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let image = image.ok_or(USAGE)?;
+
+    let mut device = OpenOptions::new()
+        .read(true)
+        .write(writable)
+        .open(&image)
+        .map_err(|e| format!("{}: {}", image, e))?;
+
+    let mut raw = [0u8; FAT_START_ADDR as usize];
+    let header = device.read(&mut raw)?;
+
+    let opened = if writable {
+        FileSystemBasicIO::open_file_system(device)
+    } else {
+        FileSystemBasicIO::open_read_only(device)
+    };
+
+    let io = match opened {
+        Ok(io) => io,
+        Err(e) => {
+            hexdump(&raw[..header], 0);
+            return Err(format!("{}: {}", image, e).into());
+        }
+    };
+
+    let mut debugfs = Debugfs {
+        volume: Volume::new(io),
+        writable,
+    };
+
+    if let Some(request) = request {
+        debugfs.execute(&request)?;
+        return Ok(0);
+    }
+
+    let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    let mut failed = false;
+    let mut lines = io::stdin().lock().lines();
+
+    loop {
+        if interactive {
+            print!("debugfs: ");
+            io::stdout().flush()?;
+        }
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+
+        match debugfs.execute(&line) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                failed = true;
+            }
+        }
+    }
+
+    Ok(if failed && !interactive { 1 } else { 0 })
+}
+
+fn main() {
+    match run() {
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("debugfs-nathfat: {}", e);
+            exit(1);
+        }
+    }
+}
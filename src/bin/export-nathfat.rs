@@ -0,0 +1,69 @@
+use std::{env, error::Error, fs::File, path::PathBuf, process::exit};
+
+use naths_fat_fs::{
+    export::export,
+    fs::{basic_fs_io::FileSystemBasicIO, volume::Volume},
+};
+
+const USAGE: &str = "usage: export-nathfat [-p|--path PATH] IMAGE DEST";
+
+const EXIT_OK: i32 = 0;
+const EXIT_ERROR: i32 = 1;
+const EXIT_SKIPPED: i32 = 2;
+
+fn run() -> Result<i32, Box<dyn Error>> {
+    let mut subtree = String::from("/");
+    let mut positional = vec![];
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" | "--path" => subtree = args.next().ok_or(USAGE)?,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(EXIT_OK);
+            }
+            _ if !arg.starts_with('-') => positional.push(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let (image, dest) = match positional.as_slice() {
+        [image, dest] => (image.clone(), PathBuf::from(dest)),
+        _ => return Err(USAGE.into()),
+    };
+
+    let device = File::open(&image).map_err(|e| format!("{}: {}", image, e))?;
+
+    let mut volume = Volume::new(
+        FileSystemBasicIO::open_read_only(device).map_err(|e| format!("{}: {}", image, e))?,
+    );
+
+    let (c, i) = volume
+        .lookup(&subtree)
+        .map_err(|e| format!("{}: {}", subtree, e))?;
+
+    let skipped =
+        export(&mut volume.fs, c, i, &dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+
+    for e in &skipped {
+        eprintln!("export-nathfat: skipping {}", e);
+    }
+
+    if skipped.is_empty() {
+        Ok(EXIT_OK)
+    } else {
+        Ok(EXIT_SKIPPED)
+    }
+}
+
+fn main() {
+    match run() {
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("export-nathfat: {}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{env, error::Error, fs::OpenOptions, process::exit};
+
+use naths_fat_fs::{
+    fs::{basic_fs_io::FileSystemBasicIO, FileSystem},
+    fsck::check,
+};
+
+const USAGE: &str = "usage: fsck-nathfat [-n|--no-action] [-y|-a|-p|--repair] DEVICE";
+
+const EXIT_CLEAN: i32 = 0;
+const EXIT_REPAIRED: i32 = 1;
+const EXIT_UNCORRECTED: i32 = 4;
+const EXIT_ERROR: i32 = 8;
+
+fn run() -> Result<i32, Box<dyn Error>> {
+    let mut repair = false;
+    let mut device = None;
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "-n" | "--no-action" => repair = false,
+            "-y" | "-a" | "-p" | "--repair" => repair = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(EXIT_CLEAN);
+            }
+            _ if device.is_none() && !arg.starts_with('-') => device = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let path = device.ok_or(USAGE)?;
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(repair)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    let io = if repair {
+        FileSystemBasicIO::open_file_system(&mut file)
+    } else {
+        FileSystemBasicIO::open_read_only(&mut file)
+    }
+    .map_err(|e| format!("{}: {}", path, e))?;
+
+    let mut fs = FileSystem::new(io);
+
+    let problems = check(&mut fs, repair)?;
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    if problems.is_empty() {
+        println!("{}: clean", path);
+        return Ok(EXIT_CLEAN);
+    }
+
+    if !repair {
+        println!("{}: {} problems found", path, problems.len());
+        return Ok(EXIT_UNCORRECTED);
+    }
+
+    file.sync_all()?;
+
+    let remaining = check(
+        &mut FileSystem::new(FileSystemBasicIO::open_file_system(&mut file)?),
+        false,
+    )?;
+
+    if remaining.is_empty() {
+        println!("{}: {} problems repaired", path, problems.len());
+        Ok(EXIT_REPAIRED)
+    } else {
+        println!(
+            "{}: {} problems could not be repaired",
+            path,
+            remaining.len()
+        );
+        Ok(EXIT_UNCORRECTED)
+    }
+}
+
+fn main() {
+    match run() {
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("fsck-nathfat: {}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+}
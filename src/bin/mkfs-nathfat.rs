@@ -0,0 +1,260 @@
+use std::{
+    env,
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::{fs::FileTypeExt, io::AsRawFd},
+    path::PathBuf,
+    process::exit,
+};
+
+use naths_fat_fs::{
+    consts::{DEFAULT_CLUSTER_SIZE, FAT_ENTRY_SIZE, FAT_START_ADDR, FS_ID, MIN_JOURNAL_CLUSTERS},
+    fs::{
+        basic_fs_io::FileSystemBasicIO,
+        cached_io::{CachedIO, DEFAULT_CACHE_SIZE},
+        FileSystem,
+    },
+    mkfs::{clusters_for_dir, create_journal, format, populate, quick_format},
+    utility::fs_utility::{
+        check_cluster_size, get_data_section_address, get_fat_size_for_image, get_image_size,
+    },
+};
+
+const USAGE: &str = "usage: mkfs-nathfat [-f|--force] [-q|--quick] [--discard] \
+    [-b|--cluster-size SIZE] [-s|--size SIZE | -c|--clusters COUNT] [-j|--journal SIZE] \
+    [-d|--root-directory DIR] DEVICE";
+
+// _IO(0x12, 119) from <linux/fs.h>
+const BLKDISCARD: libc::c_ulong = 0x1277;
+
+fn parse_size(size: &str) -> Option<u64> {
+    let (number, factor) = match size.chars().last()? {
+        'K' | 'k' => (&size[..size.len() - 1], 1 << 10),
+        'M' | 'm' => (&size[..size.len() - 1], 1 << 20),
+        'G' | 'g' => (&size[..size.len() - 1], 1 << 30),
+        'T' | 't' => (&size[..size.len() - 1], 1 << 40),
+        _ => (size, 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+fn discard(device: &File, len: u64) -> io::Result<()> {
+    let range: [u64; 2] = [0, len];
+
+    if unsafe { libc::ioctl(device.as_raw_fd(), BLKDISCARD, &range) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut force = false;
+    let mut quick = false;
+    let mut discard_blocks = false;
+    let mut size = None;
+    let mut clusters = None;
+    let mut cluster_size = DEFAULT_CLUSTER_SIZE;
+    let mut root_directory = None;
+    let mut journal_size = None;
+    let mut device = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" | "--force" => force = true,
+            "-q" | "--quick" => quick = true,
+            "--discard" => discard_blocks = true,
+            "-s" | "--size" => {
+                let value = args.next().ok_or(USAGE)?;
+                size = Some(parse_size(&value).ok_or(format!("invalid size: {}", value))?);
+            }
+            "-b" | "--cluster-size" => {
+                let value = args.next().ok_or(USAGE)?;
+                cluster_size = parse_size(&value)
+                    .and_then(|size| u32::try_from(size).ok())
+                    .ok_or(format!("invalid cluster size: {}", value))?;
+                check_cluster_size(cluster_size)?;
+            }
+            "-c" | "--clusters" => {
+                let value = args.next().ok_or(USAGE)?;
+                clusters = Some(
+                    value
+                        .parse::<u32>()
+                        .map_err(|_| format!("invalid cluster count: {}", value))?,
+                );
+            }
+            "-j" | "--journal" => {
+                let value = args.next().ok_or(USAGE)?;
+                journal_size =
+                    Some(parse_size(&value).ok_or(format!("invalid journal size: {}", value))?);
+            }
+            "-d" | "--root-directory" => {
+                root_directory = Some(PathBuf::from(args.next().ok_or(USAGE)?))
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if device.is_none() && !arg.starts_with('-') => device = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let path = device.ok_or(USAGE)?;
+
+    if size.is_some() && clusters.is_some() {
+        return Err("--size and --clusters are mutually exclusive".into());
+    }
+
+    let mut dest = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+
+    let block_device = dest.metadata()?.file_type().is_block_device();
+    let device_size = dest.seek(SeekFrom::End(0))?;
+
+    if device_size >= FS_ID.len() as u64 && !force {
+        let mut magic = [0u8; 9];
+
+        dest.rewind()?;
+        dest.read_exact(&mut magic)?;
+
+        if magic == FS_ID {
+            return Err(format!(
+                "{} already contains a NathFATfs filesystem, use --force to overwrite it",
+                path
+            )
+            .into());
+        }
+    }
+
+    let journal_clusters = journal_size.map(|size| {
+        u32::try_from(size.div_ceil(cluster_size as u64))
+            .unwrap_or(u32::MAX)
+            .max(MIN_JOURNAL_CLUSTERS)
+    });
+
+    let needed = match &root_directory {
+        Some(dir) => {
+            clusters_for_dir(dir, cluster_size).map_err(|e| format!("{}: {}", dir.display(), e))?
+        }
+        None => 1,
+    }
+    .saturating_add(journal_clusters.unwrap_or(0));
+
+    let fat_size = match (clusters, size) {
+        (Some(clusters), _) => clusters,
+        (None, Some(size)) => get_fat_size_for_image(size, cluster_size),
+        (None, None) if device_size > 0 => get_fat_size_for_image(device_size, cluster_size),
+        (None, None) if root_directory.is_some() => needed,
+        (None, None) => return Err("either --size or --clusters is required".into()),
+    };
+
+    if fat_size < 1 {
+        return Err("image too small for a single cluster".into());
+    }
+
+    if fat_size < needed {
+        return Err(format!(
+            "{} clusters are too few, {} needs {}",
+            fat_size,
+            match &root_directory {
+                Some(dir) => dir.display().to_string(),
+                None => String::from("the journal"),
+            },
+            needed
+        )
+        .into());
+    }
+
+    let image_size = get_image_size(fat_size, cluster_size);
+
+    if block_device && image_size > device_size {
+        return Err(format!(
+            "{} needs {} bytes but the device only has {}",
+            path, image_size, device_size
+        )
+        .into());
+    }
+
+    if discard_blocks {
+        if !block_device {
+            return Err("--discard only works on block devices".into());
+        }
+
+        discard(&dest, image_size).map_err(|e| format!("discard failed: {}", e))?;
+    }
+
+    if quick {
+        if !block_device && device_size < image_size {
+            dest.set_len(image_size)?;
+        }
+
+        quick_format(fat_size, cluster_size, &mut dest)?;
+    } else {
+        format(fat_size, cluster_size, &mut dest)?;
+    }
+
+    if let Some(clusters) = journal_clusters {
+        create_journal(clusters, &mut dest)?;
+    }
+
+    if let Some(dir) = &root_directory {
+        let io = CachedIO::new(
+            FileSystemBasicIO::open_file_system(&mut dest)?,
+            DEFAULT_CACHE_SIZE,
+        )?;
+        let mut fs = FileSystem::new(io);
+
+        for path in populate(&mut fs, dir)? {
+            eprintln!("mkfs-nathfat: skipping {}", path.display());
+        }
+
+        fs.into_inner().into_inner()?;
+    }
+
+    dest.sync_all()?;
+
+    let data_start = get_data_section_address(fat_size);
+
+    println!("NathFATfs created on {}", path);
+    println!("  cluster size: {} bytes", cluster_size);
+    println!("  clusters:     {}", fat_size);
+    println!(
+        "  FAT:          {:#010X} - {:#010X} ({} bytes)",
+        FAT_START_ADDR,
+        FAT_START_ADDR + fat_size as u64 * FAT_ENTRY_SIZE as u64,
+        fat_size as u64 * FAT_ENTRY_SIZE as u64
+    );
+    println!(
+        "  data region:  {:#010X} - {:#010X} ({} bytes)",
+        data_start,
+        image_size,
+        image_size - data_start
+    );
+    if let Some(clusters) = journal_clusters {
+        println!(
+            "  journal:      clusters {} - {} ({} bytes)",
+            fat_size - clusters + 1,
+            fat_size,
+            clusters as u64 * cluster_size as u64
+        );
+    }
+    println!("  image size:   {} bytes", image_size);
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("mkfs-nathfat: {}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    env,
+    error::Error,
+    fs::OpenOptions,
+    io,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use fuser::{MountOption, Session};
+use naths_fat_fs::fs::{
+    basic_fs_io::FileSystemBasicIO,
+    cached_io::{CachedIO, DEFAULT_CACHE_SIZE},
+    filesystem::AttrOverrides,
+    FileSystem,
+};
+
+const USAGE: &str = "usage: mount-nathfat [--foreground] [-o OPTION[,OPTION...]] IMAGE MOUNTPOINT";
+
+struct Args {
+    foreground: bool,
+    options: Vec<String>,
+    image: String,
+    mountpoint: PathBuf,
+}
+
+struct Options {
+    mount: Vec<MountOption>,
+    overrides: AttrOverrides,
+    cache_size: usize,
+    read_only: bool,
+}
+
+fn parse_options(list: &[String]) -> Result<Options, Box<dyn Error>> {
+    let mut options = Options {
+        mount: vec![],
+        overrides: AttrOverrides::default(),
+        cache_size: DEFAULT_CACHE_SIZE,
+        read_only: false,
+    };
+
+    for option in list.iter().flat_map(|o| o.split(',')) {
+        let option = match option {
+            "" | "defaults" | "auto" | "noauto" | "nofail" | "user" | "users" | "nouser"
+            | "_netdev" => continue,
+            "ro" => {
+                options.read_only = true;
+                MountOption::RO
+            }
+            "rw" => {
+                options.read_only = false;
+                MountOption::RW
+            }
+            "allow_other" => MountOption::AllowOther,
+            "allow_root" => MountOption::AllowRoot,
+            "auto_unmount" => MountOption::AutoUnmount,
+            "default_permissions" => MountOption::DefaultPermissions,
+            "dev" => MountOption::Dev,
+            "nodev" => MountOption::NoDev,
+            "suid" => MountOption::Suid,
+            "nosuid" => MountOption::NoSuid,
+            "exec" => MountOption::Exec,
+            "noexec" => MountOption::NoExec,
+            "atime" => MountOption::Atime,
+            "noatime" => MountOption::NoAtime,
+            "dirsync" => MountOption::DirSync,
+            "sync" => MountOption::Sync,
+            "async" => MountOption::Async,
+            o if o.starts_with("uid=") => {
+                options.overrides.uid = Some(o[4..].parse().map_err(|_| invalid(o))?);
+                continue;
+            }
+            o if o.starts_with("gid=") => {
+                options.overrides.gid = Some(o[4..].parse().map_err(|_| invalid(o))?);
+                continue;
+            }
+            o if o.starts_with("umask=") => {
+                let umask = u16::from_str_radix(&o[6..], 8).map_err(|_| invalid(o))?;
+                options.overrides.umask = Some(umask & 0o7777);
+                continue;
+            }
+            o if o.starts_with("cache_size=") => {
+                options.cache_size = o[11..].parse().map_err(|_| invalid(o))?;
+                continue;
+            }
+            o if o.starts_with("fsname=") => MountOption::FSName(o[7..].to_string()),
+            o if o.starts_with("subtype=") => MountOption::Subtype(o[8..].to_string()),
+            o => MountOption::CUSTOM(o.to_string()),
+        };
+
+        options.mount.retain(|o| !conflicts(o, &option));
+        options.mount.push(option);
+    }
+
+    Ok(options)
+}
+
+fn conflicts(old: &MountOption, new: &MountOption) -> bool {
+    use MountOption::*;
+
+    matches!(
+        (old, new),
+        (RO, RW) | (RW, RO) | (AllowOther, AllowRoot) | (AllowRoot, AllowOther)
+    ) || std::mem::discriminant(old) == std::mem::discriminant(new)
+}
+
+fn invalid(option: &str) -> String {
+    format!("invalid mount option: {}", option)
+}
+
+fn daemonize() -> io::Result<()> {
+    if unsafe { libc::daemon(0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Returns `None` if only the usage was asked for.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, Box<dyn Error>> {
+    let mut foreground = false;
+    let mut option_list = vec![];
+    let mut positional: Vec<String> = vec![];
+
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--foreground" => foreground = true,
+            "-o" => option_list.push(args.next().ok_or(USAGE)?),
+            o if o.starts_with("-o") => option_list.push(o[2..].to_string()),
+            // flags passed by mount(8) to helpers: sloppy, no mtab, verbose
+            "-s" | "-n" | "-v" => (),
+            "-h" | "--help" => return Ok(None),
+            _ if !arg.starts_with('-') => positional.push(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    match positional.as_slice() {
+        [image, mountpoint] => Ok(Some(Args {
+            foreground,
+            options: option_list,
+            image: image.clone(),
+            mountpoint: PathBuf::from(mountpoint),
+        })),
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let Args {
+        foreground,
+        options,
+        image,
+        mountpoint,
+    } = match parse_args(env::args().skip(1))? {
+        Some(args) => args,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let mut options = parse_options(&options)?;
+
+    if !options
+        .mount
+        .iter()
+        .any(|o| matches!(o, MountOption::FSName(_)))
+    {
+        options.mount.push(MountOption::FSName(image.clone()));
+    }
+
+    if !options
+        .mount
+        .iter()
+        .any(|o| matches!(o, MountOption::Subtype(_)))
+    {
+        options
+            .mount
+            .push(MountOption::Subtype("nathfat".to_string()));
+    }
+
+    let device = OpenOptions::new()
+        .read(true)
+        .write(!options.read_only)
+        .open(Path::new(&image))
+        .map_err(|e| format!("{}: {}", image, e))?;
+
+    let io = if options.read_only {
+        FileSystemBasicIO::open_read_only(device)
+    } else {
+        FileSystemBasicIO::open_file_system(device)
+    }
+    .and_then(|io| CachedIO::new(io, options.cache_size))
+    .map_err(|e| format!("{}: {}", image, e))?;
+
+    let mut fs = FileSystem::new(io);
+    fs.overrides = options.overrides;
+
+    let mut session = Session::new(fs, &mountpoint, &options.mount)
+        .map_err(|e| format!("{}: {}", mountpoint.display(), e))?;
+
+    if !foreground {
+        daemonize()?;
+    }
+
+    session.run()?;
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("mount-nathfat: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(list: &[&str]) -> Result<Options, Box<dyn Error>> {
+        parse_options(&list.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn last_of_ro_and_rw_wins() {
+        let options = parse(&["ro,rw"]).unwrap();
+        assert!(!options.read_only);
+        assert_eq!(options.mount, vec![MountOption::RW]);
+
+        let options = parse(&["rw", "ro"]).unwrap();
+        assert!(options.read_only);
+        assert_eq!(options.mount, vec![MountOption::RO]);
+    }
+
+    #[test]
+    fn repeated_option_replaces_the_earlier_one() {
+        let options = parse(&["fsname=a,allow_other,fsname=b"]).unwrap();
+
+        assert_eq!(
+            options.mount,
+            vec![
+                MountOption::AllowOther,
+                MountOption::FSName("b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn owner_and_umask_are_parsed() {
+        let options = parse(&["uid=1000,gid=100,umask=022,defaults"]).unwrap();
+
+        assert_eq!(options.overrides.uid, Some(1000));
+        assert_eq!(options.overrides.gid, Some(100));
+        assert_eq!(options.overrides.umask, Some(0o22));
+        assert!(options.mount.is_empty());
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        for option in ["uid=abc", "gid=", "umask=9", "cache_size=-1"] {
+            assert!(parse(&[option]).is_err(), "{} was accepted", option);
+        }
+    }
+
+    #[test]
+    fn mount_helper_flags_are_ignored() {
+        let args = ["-s", "-n", "-v", "-o", "ro", "disk.img", "/mnt"].map(String::from);
+        let args = parse_args(args).unwrap().unwrap();
+
+        assert!(!args.foreground);
+        assert_eq!(args.options, vec!["ro"]);
+        assert_eq!(args.image, "disk.img");
+        assert_eq!(args.mountpoint, PathBuf::from("/mnt"));
+
+        assert!(parse_args(["-x", "disk.img", "/mnt"].map(String::from)).is_err());
+        assert!(parse_args(["--help"].map(String::from)).unwrap().is_none());
+    }
+}
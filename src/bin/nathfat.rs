@@ -0,0 +1,407 @@
+use std::{
+    env,
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::exit,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use naths_fat_fs::fs::{
+    basic_fs_io::FileSystemBasicIO,
+    volume::{Metadata, Volume},
+};
+
+const USAGE: &str = "usage: nathfat COMMAND IMAGE [ARGS...]
+
+commands:
+  ls [-l] [PATH...]          list directories
+  cat PATH...                write files to stdout
+  cp SRC DEST                copy a file, paths inside the image start with ::
+  mv SRC DEST                rename or move into a directory
+  rm PATH...                 remove files
+  mkdir [-p] PATH...         create directories
+  rmdir PATH...              remove empty directories
+  stat PATH...               show metadata
+  chmod MODE PATH...         set permissions (octal)
+  chown [UID][:GID] PATH...  set owner and group";
+
+type Image = Volume<FileSystemBasicIO<File>>;
+
+fn open_image(image: &str, write: bool) -> Result<Image, Box<dyn Error>> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(write)
+        .open(image)
+        .map_err(|e| format!("{}: {}", image, e))?;
+
+    let io = if write {
+        FileSystemBasicIO::open_file_system(device)
+    } else {
+        FileSystemBasicIO::open_read_only(device)
+    }
+    .map_err(|e| format!("{}: {}", image, e))?;
+
+    Ok(Volume::new(io))
+}
+
+fn image_path(path: &str) -> &str {
+    path.strip_prefix("::").unwrap_or(path)
+}
+
+fn at(path: &str) -> impl FnOnce(io::Error) -> String + '_ {
+    move |e| format!("{}: {}", path, e)
+}
+
+fn file_name(path: &str) -> &str {
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+fn join(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+fn is_dir(volume: &mut Image, path: &str) -> bool {
+    volume.metadata(path).is_ok_and(|m| m.is_dir)
+}
+
+fn mode_string(metadata: &Metadata) -> String {
+    let mut mode = String::from(if metadata.is_dir { "d" } else { "-" });
+
+    for shift in [6, 3, 0] {
+        let bits = metadata.permission >> shift;
+
+        mode.push(if bits & 4 != 0 { 'r' } else { '-' });
+        mode.push(if bits & 2 != 0 { 'w' } else { '-' });
+        mode.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+
+    mode
+}
+
+fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+fn print_entry(metadata: &Metadata, name: &str, long: bool) {
+    if long {
+        println!(
+            "{} {:>5} {:>5} {:>10} {} {}",
+            mode_string(metadata),
+            metadata.uid,
+            metadata.gid,
+            metadata.len,
+            format_time(metadata.mtime),
+            name
+        );
+    } else {
+        println!("{}", name);
+    }
+}
+
+fn ls(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let long = args.iter().any(|a| a == "-l");
+    let mut paths: Vec<&str> = args
+        .iter()
+        .filter(|a| *a != "-l")
+        .map(|a| image_path(a))
+        .collect();
+
+    if paths.is_empty() {
+        paths.push("/");
+    }
+
+    for (n, path) in paths.iter().enumerate() {
+        let metadata = volume.metadata(path).map_err(at(path))?;
+
+        if !metadata.is_dir {
+            print_entry(&metadata, path, long);
+            continue;
+        }
+
+        if paths.len() > 1 {
+            if n > 0 {
+                println!();
+            }
+
+            println!("{}:", path);
+        }
+
+        let mut entries = volume.read_dir(path).map_err(at(path))?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for entry in &entries {
+            print_entry(entry, &entry.name, long);
+        }
+    }
+
+    Ok(())
+}
+
+fn cat(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut stdout = io::stdout().lock();
+
+    for path in args.iter().map(|a| image_path(a)) {
+        let mut file = volume.open(path).map_err(at(path))?;
+        io::copy(&mut file, &mut stdout)?;
+    }
+
+    Ok(stdout.flush()?)
+}
+
+fn cp(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (src, dest) = match args {
+        [src, dest] => (src.as_str(), dest.as_str()),
+        _ => return Err(USAGE.into()),
+    };
+
+    match (src.strip_prefix("::"), dest.strip_prefix("::")) {
+        (None, Some(dest)) => {
+            let mut host = File::open(src).map_err(at(src))?;
+            let metadata = host.metadata()?;
+
+            if metadata.is_dir() {
+                return Err(at(src)(io::Error::from_raw_os_error(libc::EISDIR)).into());
+            }
+
+            let target = if is_dir(volume, dest) {
+                join(dest, file_name(src))
+            } else {
+                dest.to_string()
+            };
+
+            let mut file = volume.create(&target).map_err(at(&target))?;
+            io::copy(&mut host, &mut file)?;
+            volume.set_permissions(&target, metadata.permissions().mode() as u16)?;
+        }
+        (Some(src), None) => {
+            let mut target = PathBuf::from(dest);
+
+            if target.is_dir() {
+                target.push(file_name(src));
+            }
+
+            let mut file = volume.open(src).map_err(at(src))?;
+            let permission = file.metadata()?.permission;
+            let mut host =
+                File::create(&target).map_err(|e| format!("{}: {}", target.display(), e))?;
+
+            io::copy(&mut file, &mut host)?;
+            host.set_permissions(PermissionsExt::from_mode(permission as u32))?;
+        }
+        (Some(src), Some(dest)) => {
+            let mut data = vec![];
+            let mut file = volume.open(src).map_err(at(src))?;
+            let permission = file.metadata()?.permission;
+
+            file.read_to_end(&mut data)?;
+
+            let target = if is_dir(volume, dest) {
+                join(dest, file_name(src))
+            } else {
+                dest.to_string()
+            };
+
+            volume
+                .create(&target)
+                .map_err(at(&target))?
+                .write_all(&data)?;
+            volume.set_permissions(&target, permission)?;
+        }
+        (None, None) => return Err("SRC or DEST has to be inside the image (::PATH)".into()),
+    }
+
+    Ok(())
+}
+
+fn mv(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (src, dest) = match args {
+        [src, dest] => (image_path(src), image_path(dest)),
+        _ => return Err(USAGE.into()),
+    };
+
+    let target = if is_dir(volume, dest) {
+        join(dest, file_name(src))
+    } else {
+        dest.to_string()
+    };
+
+    Ok(volume.rename(src, &target).map_err(at(src))?)
+}
+
+fn mkdir(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let parents = args.iter().any(|a| a == "-p");
+
+    for path in args.iter().filter(|a| *a != "-p").map(|a| image_path(a)) {
+        if !parents {
+            volume.create_dir(path).map_err(at(path))?;
+            continue;
+        }
+
+        let mut prefix = String::new();
+
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            prefix = join(&prefix, name);
+
+            if !is_dir(volume, &prefix) {
+                volume.create_dir(&prefix).map_err(at(&prefix))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn rm(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    for path in args.iter().map(|a| image_path(a)) {
+        volume.remove_file(path).map_err(at(path))?;
+    }
+
+    Ok(())
+}
+
+fn rmdir(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    for path in args.iter().map(|a| image_path(a)) {
+        volume.remove_dir(path).map_err(at(path))?;
+    }
+
+    Ok(())
+}
+
+fn stat(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    for path in args.iter().map(|a| image_path(a)) {
+        let metadata = volume.metadata(path).map_err(at(path))?;
+
+        println!("  File: {}", path);
+        println!(
+            "  Type: {}",
+            if metadata.is_dir {
+                "directory"
+            } else {
+                "regular file"
+            }
+        );
+        println!("  Size: {:<12} Inode: {}", metadata.len, metadata.ino);
+        println!(
+            "Access: ({:04o}/{})  Uid: {}  Gid: {}",
+            metadata.permission,
+            mode_string(&metadata),
+            metadata.uid,
+            metadata.gid
+        );
+        println!("Access: {}", format_time(metadata.atime));
+        println!("Modify: {}", format_time(metadata.mtime));
+        println!("Change: {}", format_time(metadata.ctime));
+    }
+
+    Ok(())
+}
+
+fn chmod(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (mode, paths) = args.split_first().ok_or(USAGE)?;
+    let mode = u16::from_str_radix(mode, 8).map_err(|_| format!("invalid mode: {}", mode))?;
+
+    for path in paths {
+        volume
+            .set_permissions(image_path(path), mode)
+            .map_err(at(path))?;
+    }
+
+    Ok(())
+}
+
+fn chown(volume: &mut Image, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (owner, paths) = args.split_first().ok_or(USAGE)?;
+    let invalid = || format!("invalid owner: {}", owner);
+
+    let (uid, gid) = owner.split_once(':').unwrap_or((owner, ""));
+    let uid = match uid {
+        "" => None,
+        uid => Some(uid.parse().map_err(|_| invalid())?),
+    };
+    let gid = match gid {
+        "" => None,
+        gid => Some(gid.parse().map_err(|_| invalid())?),
+    };
+
+    for path in paths {
+        volume
+            .set_owner(image_path(path), uid, gid)
+            .map_err(at(path))?;
+    }
+
+    Ok(())
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args();
+
+    let program = args.next().unwrap_or_default();
+    let program = Path::new(&program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("nathfat");
+
+    // also callable as nathfat-ls, nathfat-cp, ... through symlinks
+    let command = match program.strip_prefix("nathfat-") {
+        Some(command) => command.to_string(),
+        None => args.next().ok_or(USAGE)?,
+    };
+
+    if command == "-h" || command == "--help" {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let image = args.next().ok_or(USAGE)?;
+    let args: Vec<String> = args.collect();
+
+    let write = match command.as_str() {
+        "ls" | "cat" | "stat" => false,
+        "cp" => args.get(1).is_some_and(|dest| dest.starts_with("::")),
+        _ => true,
+    };
+
+    let operation = match command.as_str() {
+        "ls" => ls,
+        "cat" => cat,
+        "cp" => cp,
+        "mv" => mv,
+        "rm" => rm,
+        "mkdir" => mkdir,
+        "rmdir" => rmdir,
+        "stat" => stat,
+        "chmod" => chmod,
+        "chown" => chown,
+        _ => return Err(format!("unknown command: {}\n{}", command, USAGE).into()),
+    };
+
+    let mut volume = open_image(&image, write)?;
+
+    operation(&mut volume, &args)
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("nathfat: {}", e);
+        exit(1);
+    }
+}
@@ -1,11 +1,21 @@
 use crate::FatEntry;
 
 pub const FS_ID: [u8; 9] = *b"NathFATfs";
-pub const FS_VERSION: [u8; 1] = [1u8];
+pub const FS_VERSION: [u8; 1] = [3u8];
 
 pub const FAT_ENTRY_SIZE: u32 = 4;
 pub const DIR_ENTRY_SIZE: u32 = 64;
-pub const CLUSTER_SIZE: u32 = 1024; // 8192;
+pub const INODE_NAME_SIZE: u32 = 25;
+pub const LONG_NAME_SIZE: u32 = 63;
+pub const NAME_MAX: u32 = 255;
+
+pub const DEFAULT_CLUSTER_SIZE: u32 = 4096;
+pub const MIN_CLUSTER_SIZE: u32 = 512;
+pub const MAX_CLUSTER_SIZE: u32 = 65536;
+pub const LEGACY_CLUSTER_SIZE: u32 = 1024; // version 1 images
+
+pub const JOURNAL_MAGIC: [u8; 4] = *b"NFJL";
+pub const MIN_JOURNAL_CLUSTERS: u32 = 2; // header + payload
 
 pub const FAT_START_ADDR: u64 = 32;
 pub const ALIGNMENT: u32 = 32;
@@ -0,0 +1,85 @@
+use std::{fmt::Display, io, str::Utf8Error};
+
+use libc::{c_int, EIO, ELOOP, ENOENT, ENOSPC, EUCLEAN};
+
+use crate::FatEntry;
+
+#[derive(Debug)]
+pub enum FsError {
+    Io(io::Error),
+    BadCluster(FatEntry),
+    EndOfChain,
+    NoSpace,
+    BadMagic([u8; 9]),
+    BadVersion(u8),
+    BadClusterSize(u32),
+    BadName(Utf8Error),
+    DirectoryLoop(FatEntry),
+    BadJournal(FatEntry, u32),
+    JournalFull,
+}
+
+impl Display for FsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsError::Io(e) => write!(f, "i/o error: {}", e),
+            FsError::BadCluster(cluster) => write!(f, "bad cluster {:#010X}", cluster),
+            FsError::EndOfChain => write!(f, "end of chain"),
+            FsError::NoSpace => write!(f, "no free cluster left"),
+            FsError::BadMagic(magic) => {
+                write!(f, "invalid filesystem: {}", String::from_utf8_lossy(magic))
+            }
+            FsError::BadVersion(version) => write!(f, "invalid filesystem version {}", version),
+            FsError::BadClusterSize(size) => write!(f, "invalid cluster size {}", size),
+            FsError::BadName(e) => write!(f, "invalid file name: {}", e),
+            FsError::DirectoryLoop(cluster) => {
+                write!(f, "directory loop at cluster {:#010X}", cluster)
+            }
+            FsError::BadJournal(start, length) => write!(
+                f,
+                "invalid journal of {} clusters at cluster {:#010X}",
+                length, start
+            ),
+            FsError::JournalFull => write!(f, "operation too big for the journal"),
+        }
+    }
+}
+
+impl std::error::Error for FsError {}
+
+impl From<io::Error> for FsError {
+    fn from(value: io::Error) -> Self {
+        FsError::Io(value)
+    }
+}
+
+impl From<Utf8Error> for FsError {
+    fn from(value: Utf8Error) -> Self {
+        FsError::BadName(value)
+    }
+}
+
+impl From<FsError> for c_int {
+    fn from(value: FsError) -> Self {
+        match value {
+            FsError::Io(_) => EIO,
+            FsError::EndOfChain => ENOENT,
+            FsError::NoSpace | FsError::JournalFull => ENOSPC,
+            FsError::DirectoryLoop(_) => ELOOP,
+            _ => EUCLEAN,
+        }
+    }
+}
+
+impl From<FsError> for io::Error {
+    fn from(value: FsError) -> Self {
+        match value {
+            FsError::Io(e) => e,
+            FsError::EndOfChain
+            | FsError::NoSpace
+            | FsError::JournalFull
+            | FsError::DirectoryLoop(_) => io::Error::from_raw_os_error(c_int::from(value)),
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    fs::{self, File, FileTimes, Permissions},
+    io::{self, Write},
+    os::unix::fs::{chown, PermissionsExt},
+    path::Path,
+};
+
+use crate::{
+    error::FsError,
+    fs::{
+        basic_fs_io::BaseIO,
+        directory::{DirectoryEntry, Inode},
+        walk::{WalkError, WalkOrder},
+        FileSystem,
+    },
+    FatEntry,
+};
+
+const READ_CHUNK: u32 = 1 << 20;
+
+/// Copies everything below the directory whose entry is at `cluster`/`idx` into
+/// the host directory `dest`, restoring permissions, timestamps and, when
+/// running as root, ownership. Entries that can't be read or written are
+/// skipped and returned, only problems with `dest` itself abort the export.
+pub fn export<B: BaseIO>(
+    fs: &mut FileSystem<B>,
+    cluster: FatEntry,
+    idx: u32,
+    dest: &Path,
+) -> io::Result<Vec<WalkError>> {
+    fs::create_dir_all(dest)?;
+
+    let mut skipped = vec![];
+    let mut entries = vec![];
+
+    let mut walk = fs
+        .walk_from(cluster, idx, "", WalkOrder::DepthFirst)
+        .map_err(io::Error::from_raw_os_error)?;
+
+    while let Some(result) = walk.next() {
+        // names come from the image, one like `..` must not lead out of `dest`
+        if let Ok((path, DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode), _)) =
+            &result
+        {
+            if !is_safe_name(&inode.name) {
+                walk.prune();
+                skipped.push(WalkError {
+                    path: path.clone(),
+                    error: FsError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unsafe file name {:?}", inode.name),
+                    )),
+                });
+                continue;
+            }
+        }
+
+        entries.push(result);
+    }
+
+    let owner = unsafe { libc::geteuid() } == 0;
+
+    let mut dirs = vec![];
+
+    for result in entries {
+        let (path, entry) = match result {
+            Ok((path, entry, _)) => (path, entry),
+            Err(e) => {
+                skipped.push(e);
+                continue;
+            }
+        };
+
+        let host_path = dest.join(path.trim_start_matches('/'));
+
+        let result = match &entry {
+            DirectoryEntry::Directory(_) => fs::create_dir(&host_path)
+                .or_else(|e| match e.kind() {
+                    io::ErrorKind::AlreadyExists if host_path.is_dir() => Ok(()),
+                    _ => Err(e),
+                })
+                .map_err(FsError::from),
+            DirectoryEntry::File(inode) => export_file(fs, inode, &host_path)
+                .and_then(|()| Ok(restore_attributes(&host_path, inode, owner)?)),
+            _ => continue,
+        };
+
+        match result {
+            Ok(()) => {
+                if let DirectoryEntry::Directory(inode) = entry {
+                    dirs.push((host_path, inode, path));
+                }
+            }
+            Err(error) => skipped.push(WalkError { path, error }),
+        }
+    }
+
+    // children first, so neither their creation nor a read-only parent gets in the way
+    for (host_path, inode, path) in dirs.into_iter().rev() {
+        if let Err(e) = restore_attributes(&host_path, &inode, owner) {
+            skipped.push(WalkError {
+                path,
+                error: FsError::Io(e),
+            });
+        }
+    }
+
+    Ok(skipped)
+}
+
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\0'])
+}
+
+fn export_file<B: BaseIO>(
+    fs: &mut FileSystem<B>,
+    inode: &Inode,
+    host_path: &Path,
+) -> Result<(), FsError> {
+    let mut offset = 0;
+    let mut chunk = fs.read_data(inode, offset, READ_CHUNK)?;
+    let mut file = File::create(host_path)?;
+
+    while !chunk.is_empty() {
+        file.write_all(&chunk)?;
+        offset += chunk.len() as u64;
+
+        chunk = fs.read_data(inode, offset, READ_CHUNK)?;
+    }
+
+    Ok(())
+}
+
+fn restore_attributes(host_path: &Path, inode: &Inode, owner: bool) -> io::Result<()> {
+    if owner {
+        chown(host_path, Some(inode.uid), Some(inode.gid))?;
+    }
+
+    let times = FileTimes::new()
+        .set_accessed(inode.atime)
+        .set_modified(inode.mtime);
+
+    File::open(host_path)?.set_times(times)?;
+
+    fs::set_permissions(host_path, Permissions::from_mode(inode.permission as u32))
+}
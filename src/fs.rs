@@ -1,85 +1,276 @@
-use std::io::{Read, Seek, Write};
+use std::{
+    cmp::{max, min},
+    collections::HashSet,
+    time::SystemTime,
+};
+
+use libc::{
+    c_int, EEXIST, EINVAL, EISDIR, ENAMETOOLONG, ENOENT, ENOTDIR, ENOTEMPTY, RENAME_EXCHANGE,
+    RENAME_NOREPLACE,
+};
 
 use crate::{
-    consts::{CLUSTER_SIZE, DIR_ENTRY_SIZE, EOC, FRE},
-    Chain, Dir, DirEntry, FatEntry,
+    consts::{DIR_ENTRY_SIZE, EOC, FRE, NAME_MAX},
+    error::FsError,
+    utility::fs_utility::check_cluster,
+    Chain, Cluster, Dir, DirEntry, FatEntry,
 };
 
 use self::{
-    basic_fs_io::{BaseIO, FileSystemBasicIO},
-    directory::DirectoryEntry,
+    basic_fs_io::BaseIO,
+    directory::{DirectoryEntry, Inode},
+    filesystem::AttrOverrides,
+    free_map::FreeMap,
+    inode_map::InodeMap,
+    journal::Transaction,
+    walk::{Walk, WalkOrder},
 };
 
+pub mod background;
 pub mod basic_fs_io;
+pub mod cached_io;
 pub mod directory;
 pub mod filesystem;
+pub mod free_map;
+pub mod inode_map;
+pub mod journal;
+pub mod shared_io;
+pub mod volume;
+pub mod walk;
 
-pub struct FileSystem<'a, T>
+const FAT_READ_CHUNK: u32 = 16384;
+
+pub struct FileSystem<B>
 where
-    T: Read + Seek + Write,
+    B: BaseIO,
 {
-    pub io: FileSystemBasicIO<'a, T>,
+    pub io: B,
+    pub inodes: InodeMap,
+    pub overrides: AttrOverrides,
+    free_map: Option<FreeMap>,
+    used_entries: Option<u64>,
+    journal: Option<(FatEntry, u32)>,
+    transaction: Option<Transaction>,
+    // FAT writes bypass the transaction while a chain grows
+    linking: bool,
 }
 
-impl<'a, T> FileSystem<'a, T>
+impl<B> FileSystem<B>
 where
-    T: Read + Seek + Write,
+    B: BaseIO,
 {
-    pub fn alloc_chunk(&mut self) -> FatEntry {
-        let mut next = 1;
+    pub fn new(io: B) -> Self {
+        let entries_per_cluster = io.entries_per_cluster();
+        let journal = io.journal();
+
+        FileSystem {
+            io,
+            inodes: InodeMap::new(entries_per_cluster),
+            overrides: AttrOverrides::default(),
+            free_map: None,
+            used_entries: None,
+            journal,
+            transaction: None,
+            linking: false,
+        }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.io
+    }
+
+    /// Runs `op` as one transaction. On images with a journal the metadata it
+    /// writes reaches the disk completely or not at all, nested calls join the
+    /// outer transaction.
+    pub fn transaction<T, E>(&mut self, op: impl FnOnce(&mut Self) -> Result<T, E>) -> Result<T, E>
+    where
+        E: From<FsError>,
+    {
+        if self.journal.is_none() || self.transaction.is_some() {
+            return op(self);
+        }
+
+        self.transaction = Some(Transaction::default());
+        let inodes = self.inodes.clone();
+
+        let result = op(self).and_then(|value| {
+            self.commit()?;
+            Ok(value)
+        });
+
+        // forget what the operation changed in memory when it didn't make it to the disk
+        if result.is_err() {
+            self.transaction = None;
+            self.inodes = inodes;
+            self.free_map = None;
+            self.used_entries = None;
+        }
+
+        result
+    }
+
+    fn commit(&mut self) -> Result<(), FsError> {
+        let (transaction, (start, length)) = match (self.transaction.take(), self.journal) {
+            (Some(transaction), Some(journal)) if !transaction.is_empty() => (transaction, journal),
+            _ => return Ok(()),
+        };
+
+        match (
+            journal::commit(&mut self.io, start, length, &transaction),
+            &mut self.free_map,
+        ) {
+            (Err(e), _) => {
+                self.free_map = None;
+                return Err(e);
+            }
+            (Ok(()), Some(map)) => {
+                for (cluster, _) in transaction.fat.iter().filter(|(_, e)| **e == FRE) {
+                    map.set_free(*cluster, true);
+                }
+            }
+            (Ok(()), None) => (),
+        }
+
+        Ok(())
+    }
+
+    /// Walks the whole tree below the root directory.
+    pub fn walk(&mut self, order: WalkOrder) -> Walk<'_, B> {
+        Walk::new(self, "/", 1, order)
+    }
+
+    /// Walks the tree below the directory whose entry is at `cluster`/`idx`,
+    /// prefixing every path with `path`.
+    pub fn walk_from(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+        path: &str,
+        order: WalkOrder,
+    ) -> Result<Walk<'_, B>, c_int> {
+        let start_cluster = match self.read_directory_entry(cluster, idx)? {
+            DirectoryEntry::Directory(inode) => inode.start_cluster,
+            _ => return Err(ENOTDIR),
+        };
+
+        Ok(Walk::new(self, path, start_cluster, order))
+    }
+
+    fn free_map(&mut self) -> Result<&mut FreeMap, FsError> {
+        let map = match self.free_map.take() {
+            Some(map) => map,
+            None => {
+                let mut map = FreeMap::new(self.io.fat_length());
+                let mut cluster = 1;
+
+                while cluster <= self.io.fat_length() {
+                    let count = min(FAT_READ_CHUNK, self.io.fat_length() - cluster + 1);
+
+                    for entry in self.io.read_fat(cluster, count)? {
+                        map.set_free(cluster, entry == FRE);
+                        cluster += 1;
+                    }
+                }
+
+                map
+            }
+        };
 
-        while self.read_fat_entry(next) != FRE {
-            next += 1;
+        Ok(self.free_map.insert(map))
+    }
+
+    pub fn free_clusters(&mut self) -> Result<u32, FsError> {
+        Ok(self.free_map()?.free())
+    }
+
+    /// Number of files and directories including the root. The tree is only
+    /// walked once, afterwards the count is kept current like the free map.
+    pub fn used_entries(&mut self) -> Result<u64, FsError> {
+        if let Some(count) = self.used_entries {
+            return Ok(count);
         }
 
-        self.write_fat_entry(next, EOC);
+        let count = 1 + self
+            .walk(WalkOrder::DepthFirst)
+            .filter(Result::is_ok)
+            .count() as u64;
+
+        Ok(*self.used_entries.insert(count))
+    }
+
+    pub fn alloc_chunk(&mut self) -> Result<FatEntry, FsError> {
+        let next = self.free_map()?.next_free().ok_or(FsError::NoSpace)?;
+
+        self.write_fat_entry(next, EOC)?;
+        self.free_map()?.set_hint(next);
 
-        next
+        Ok(next)
     }
 
-    pub fn get_chain(&mut self, mut cluster: FatEntry) -> Chain {
+    pub fn get_chain(&mut self, mut cluster: FatEntry) -> Result<Chain, FsError> {
         let mut vec = vec![];
 
         while cluster != EOC {
+            if vec.len() > self.io.fat_length() as usize {
+                return Err(FsError::BadCluster(cluster));
+            }
+
             vec.push(cluster);
 
-            cluster = self.read_fat_entry(cluster);
+            cluster = self.read_fat_entry(cluster)?;
         }
 
-        vec
+        Ok(vec)
     }
 
-    pub fn append_to_chain(&mut self, chain: &mut Chain) -> FatEntry {
-        let new = self.alloc_chunk();
+    pub fn append_to_chain(&mut self, chain: &mut Chain) -> Result<FatEntry, FsError> {
+        let new = self.alloc_chunk()?;
 
         if let Some(last) = chain.last() {
-            self.write_fat_entry(*last, new)
+            self.write_fat_entry(*last, new)?;
         }
 
         chain.push(new);
 
-        new
+        Ok(new)
     }
 
-    pub fn append_dir_to_chain(&mut self, chain: &mut Chain) -> FatEntry {
-        let new = self.append_to_chain(chain);
+    pub fn append_dir_to_chain(&mut self, chain: &mut Chain) -> Result<FatEntry, FsError> {
+        let new = self.append_to_chain(chain)?;
 
-        for i in 0..(CLUSTER_SIZE / DIR_ENTRY_SIZE) {
-            self.write_raw_directory_entry(new, i, &DirEntry::from(&DirectoryEntry::Invalid));
+        for i in 0..self.io.entries_per_cluster() {
+            self.write_directory_entry(new, i, &DirectoryEntry::Invalid)?;
         }
 
-        new
+        Ok(new)
     }
 
-    pub fn read_dir(&mut self, chain: &Chain) -> Dir {
+    pub fn read_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+    ) -> Result<DirectoryEntry, FsError> {
+        DirectoryEntry::try_from(&self.read_raw_directory_entry(cluster, idx)?)
+    }
+
+    pub fn write_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+        entry: &DirectoryEntry,
+    ) -> Result<(), FsError> {
+        self.write_raw_directory_entry(cluster, idx, &DirEntry::from(entry))
+    }
+
+    pub fn read_dir(&mut self, chain: &Chain) -> Result<Dir, FsError> {
         let mut dir = vec![];
 
         let mut filename = String::new();
+        let entries_per_cluster = self.io.entries_per_cluster();
 
         for i in chain {
-            for j in 0..(CLUSTER_SIZE / DIR_ENTRY_SIZE) {
-                let raw = self.read_raw_directory_entry(*i, j);
-                let mut entry = DirectoryEntry::from(&raw);
+            for j in 0..entries_per_cluster {
+                let mut entry = self.read_directory_entry(*i, j)?;
 
                 match &mut entry {
                     DirectoryEntry::LongFileName(str) => filename = format!("{}{}", filename, str),
@@ -97,35 +288,807 @@ where
                 dir.push((entry, *i, j));
             }
         }
-        dir
+        Ok(dir)
+    }
+
+    pub fn find_in_dir(
+        &mut self,
+        chain: &Chain,
+        name: &str,
+    ) -> Result<Option<(DirectoryEntry, FatEntry, u32)>, FsError> {
+        Ok(self
+            .read_dir(chain)?
+            .into_iter()
+            .find(|(entry, _, _)| match entry {
+                DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) => {
+                    inode.name == name
+                }
+                _ => false,
+            }))
+    }
+
+    pub fn find_free_entries(
+        &mut self,
+        chain: &mut Chain,
+        count: usize,
+    ) -> Result<Vec<(FatEntry, u32)>, FsError> {
+        loop {
+            let mut run = vec![];
+
+            for (entry, c, i) in self.read_dir(chain)? {
+                match entry {
+                    DirectoryEntry::Invalid => {
+                        run.push((c, i));
+
+                        if run.len() == count {
+                            return Ok(run);
+                        }
+                    }
+                    _ => run.clear(),
+                }
+            }
+
+            self.append_dir_to_chain(chain)?;
+        }
+    }
+
+    pub fn insert_entry(
+        &mut self,
+        chain: &mut Chain,
+        entry: &DirectoryEntry,
+    ) -> Result<(FatEntry, u32), FsError> {
+        let raw = entry.split();
+
+        let slots = self.find_free_entries(chain, raw.len())?;
+
+        for ((c, i), raw) in slots.iter().zip(&raw) {
+            self.write_raw_directory_entry(*c, *i, raw)?;
+        }
+
+        Ok(slots[slots.len() - 1])
+    }
+
+    pub fn dir_chain(&mut self, cluster: FatEntry, idx: u32) -> Result<Chain, c_int> {
+        match self.read_directory_entry(cluster, idx)? {
+            DirectoryEntry::Directory(inode) => Ok(self.get_chain(inode.start_cluster)?),
+            _ => Err(ENOTDIR),
+        }
+    }
+
+    pub fn touch_entry(&mut self, cluster: FatEntry, idx: u32) -> Result<(), FsError> {
+        let mut entry = self.read_directory_entry(cluster, idx)?;
+
+        if let DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) = &mut entry {
+            let now = SystemTime::now();
+
+            inode.mtime = now;
+            inode.ctime = now;
+
+            self.write_directory_entry(cluster, idx, &entry)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_dot_entries(
+        &mut self,
+        cluster: FatEntry,
+        parent_cluster: FatEntry,
+        uid: u32,
+        gid: u32,
+        permission: u16,
+    ) -> Result<(), FsError> {
+        let now = SystemTime::now();
+
+        for (idx, (name, start_cluster)) in [(".", cluster), ("..", parent_cluster)]
+            .into_iter()
+            .enumerate()
+        {
+            self.write_directory_entry(
+                cluster,
+                idx as u32,
+                &DirectoryEntry::Directory(Inode::new(
+                    String::from(name),
+                    DIR_ENTRY_SIZE as u64,
+                    uid,
+                    gid,
+                    permission,
+                    now,
+                    now,
+                    now,
+                    2,
+                    start_cluster,
+                )),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn create_file(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+        uid: u32,
+        gid: u32,
+        permission: u16,
+    ) -> Result<(FatEntry, u32), c_int> {
+        self.create_entry(
+            parent_cluster,
+            parent_idx,
+            name,
+            uid,
+            gid,
+            permission,
+            false,
+        )
+    }
+
+    pub fn create_dir(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+        uid: u32,
+        gid: u32,
+        permission: u16,
+    ) -> Result<(FatEntry, u32), c_int> {
+        self.create_entry(parent_cluster, parent_idx, name, uid, gid, permission, true)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_entry(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+        uid: u32,
+        gid: u32,
+        permission: u16,
+        directory: bool,
+    ) -> Result<(FatEntry, u32), c_int> {
+        self.transaction(|fs| {
+            fs.insert_new_entry(
+                parent_cluster,
+                parent_idx,
+                name,
+                uid,
+                gid,
+                permission,
+                directory,
+            )
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn insert_new_entry(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+        uid: u32,
+        gid: u32,
+        permission: u16,
+        directory: bool,
+    ) -> Result<(FatEntry, u32), c_int> {
+        let mut chain = self.dir_chain(parent_cluster, parent_idx)?;
+
+        if name.len() > NAME_MAX as usize {
+            return Err(ENAMETOOLONG);
+        }
+
+        if self.find_in_dir(&chain, name)?.is_some() {
+            return Err(EEXIST);
+        }
+
+        let now = SystemTime::now();
+
+        let entry = if directory {
+            let start_cluster = self.append_dir_to_chain(&mut vec![])?;
+            self.write_dot_entries(start_cluster, chain[0], uid, gid, permission)?;
+
+            DirectoryEntry::Directory(Inode::new(
+                name.to_string(),
+                DIR_ENTRY_SIZE as u64,
+                uid,
+                gid,
+                permission,
+                now,
+                now,
+                now,
+                2,
+                start_cluster,
+            ))
+        } else {
+            DirectoryEntry::File(Inode::new(
+                name.to_string(),
+                0,
+                uid,
+                gid,
+                permission,
+                now,
+                now,
+                now,
+                1,
+                self.alloc_chunk()?,
+            ))
+        };
+
+        let location = self.insert_entry(&mut chain, &entry)?;
+
+        if let Some(count) = &mut self.used_entries {
+            *count += 1;
+        }
+
+        self.touch_entry(parent_cluster, parent_idx)?;
+
+        Ok(location)
+    }
+
+    pub fn free_chain(&mut self, start_cluster: FatEntry) -> Result<(), FsError> {
+        for cluster in self.get_chain(start_cluster)? {
+            self.write_fat_entry(cluster, FRE)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_dir(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+    ) -> Result<(), c_int> {
+        self.transaction(|fs| fs.unlink_dir(parent_cluster, parent_idx, name))
+    }
+
+    fn unlink_dir(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+    ) -> Result<(), c_int> {
+        let chain = self.dir_chain(parent_cluster, parent_idx)?;
+
+        let (entry, c, i) = self.find_in_dir(&chain, name)?.ok_or(ENOENT)?;
+
+        let inode = match entry {
+            DirectoryEntry::Directory(inode) => inode,
+            _ => return Err(ENOTDIR),
+        };
+
+        if inode.name == "." || inode.name == ".." {
+            return Err(EINVAL);
+        }
+
+        let content = self.get_chain(inode.start_cluster)?;
+
+        let not_empty = self.read_dir(&content)?.iter().any(|(e, _, _)| match e {
+            DirectoryEntry::Directory(d) => d.name != "." && d.name != "..",
+            DirectoryEntry::File(_) => true,
+            _ => false,
+        });
+
+        if not_empty {
+            return Err(ENOTEMPTY);
+        }
+
+        self.free_chain(inode.start_cluster)?;
+        self.invalidate_entry(&chain, c, i)?;
+        self.inodes.take(c, i);
+
+        if let Some(count) = &mut self.used_entries {
+            *count = count.saturating_sub(1);
+        }
+
+        self.touch_entry(parent_cluster, parent_idx)?;
+
+        Ok(())
+    }
+
+    pub fn remove_file(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+    ) -> Result<(), c_int> {
+        self.transaction(|fs| fs.unlink_file(parent_cluster, parent_idx, name))
+    }
+
+    fn unlink_file(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+    ) -> Result<(), c_int> {
+        let chain = self.dir_chain(parent_cluster, parent_idx)?;
+
+        let (entry, c, i) = self.find_in_dir(&chain, name)?.ok_or(ENOENT)?;
+
+        let inode = match entry {
+            DirectoryEntry::File(inode) => inode,
+            _ => return Err(EISDIR),
+        };
+
+        self.free_chain(inode.start_cluster)?;
+        self.invalidate_entry(&chain, c, i)?;
+        self.inodes.take(c, i);
+
+        if let Some(count) = &mut self.used_entries {
+            *count = count.saturating_sub(1);
+        }
+
+        self.touch_entry(parent_cluster, parent_idx)?;
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn rename(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+        new_parent_cluster: FatEntry,
+        new_parent_idx: u32,
+        new_name: &str,
+        flags: u32,
+    ) -> Result<(), c_int> {
+        self.transaction(|fs| {
+            fs.move_entry(
+                parent_cluster,
+                parent_idx,
+                name,
+                new_parent_cluster,
+                new_parent_idx,
+                new_name,
+                flags,
+            )
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn move_entry(
+        &mut self,
+        parent_cluster: FatEntry,
+        parent_idx: u32,
+        name: &str,
+        new_parent_cluster: FatEntry,
+        new_parent_idx: u32,
+        new_name: &str,
+        flags: u32,
+    ) -> Result<(), c_int> {
+        let src_start = self.dir_chain(parent_cluster, parent_idx)?[0];
+        let dst_start = self.dir_chain(new_parent_cluster, new_parent_idx)?[0];
+
+        if new_name.len() > NAME_MAX as usize {
+            return Err(ENAMETOOLONG);
+        }
+
+        if [name, new_name].iter().any(|n| *n == "." || *n == "..") {
+            return Err(EINVAL);
+        }
+
+        let src_chain = self.get_chain(src_start)?;
+        let (mut entry, sc, si) = self.find_in_dir(&src_chain, name)?.ok_or(ENOENT)?;
+
+        let dst_chain = self.get_chain(dst_start)?;
+        let target = self.find_in_dir(&dst_chain, new_name)?;
+
+        if let Some((_, tc, ti)) = &target {
+            if (*tc, *ti) == (sc, si) {
+                return Ok(());
+            }
+        }
+
+        if let DirectoryEntry::Directory(inode) = &entry {
+            if self.is_ancestor(inode.start_cluster, dst_start)? {
+                return Err(EINVAL);
+            }
+        }
+
+        if flags & RENAME_EXCHANGE != 0 {
+            let (mut other, tc, ti) = target.ok_or(ENOENT)?;
+
+            if let DirectoryEntry::Directory(inode) = &other {
+                if self.is_ancestor(inode.start_cluster, src_start)? {
+                    return Err(EINVAL);
+                }
+            }
+
+            set_name(&mut entry, new_name);
+            set_name(&mut other, name);
+
+            self.invalidate_entry(&src_chain, sc, si)?;
+            self.invalidate_entry(&dst_chain, tc, ti)?;
+
+            let mut chain = self.get_chain(dst_start)?;
+            let (ec, ei) = self.insert_entry(&mut chain, &entry)?;
+
+            let mut chain = self.get_chain(src_start)?;
+            let (oc, oi) = self.insert_entry(&mut chain, &other)?;
+
+            if src_start != dst_start {
+                if let DirectoryEntry::Directory(inode) = &entry {
+                    self.set_parent_link(inode.start_cluster, dst_start)?;
+                }
+
+                if let DirectoryEntry::Directory(inode) = &other {
+                    self.set_parent_link(inode.start_cluster, src_start)?;
+                }
+            }
+
+            let entry_inode = self.inodes.take(sc, si);
+            let other_inode = self.inodes.take(tc, ti);
+
+            self.inodes.assign(entry_inode, ec, ei);
+            self.inodes.assign(other_inode, oc, oi);
+        } else {
+            if let Some((existing, _, _)) = target {
+                if flags & RENAME_NOREPLACE != 0 {
+                    return Err(EEXIST);
+                }
+
+                match (&entry, existing) {
+                    (DirectoryEntry::Directory(_), DirectoryEntry::Directory(_)) => {
+                        self.remove_dir(new_parent_cluster, new_parent_idx, new_name)?
+                    }
+                    (DirectoryEntry::Directory(_), _) => return Err(ENOTDIR),
+                    (_, DirectoryEntry::Directory(_)) => return Err(EISDIR),
+                    _ => self.remove_file(new_parent_cluster, new_parent_idx, new_name)?,
+                }
+            }
+
+            set_name(&mut entry, new_name);
+
+            let mut chain = self.get_chain(dst_start)?;
+            let (nc, ni) = self.insert_entry(&mut chain, &entry)?;
+
+            let chain = self.get_chain(src_start)?;
+            self.invalidate_entry(&chain, sc, si)?;
+
+            if let DirectoryEntry::Directory(inode) = &entry {
+                if src_start != dst_start {
+                    self.set_parent_link(inode.start_cluster, dst_start)?;
+                }
+            }
+
+            self.inodes.moved((sc, si), (nc, ni));
+        }
+
+        self.touch_entry(parent_cluster, parent_idx)?;
+
+        if (parent_cluster, parent_idx) != (new_parent_cluster, new_parent_idx) {
+            self.touch_entry(new_parent_cluster, new_parent_idx)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_parent_link(
+        &mut self,
+        dir_cluster: FatEntry,
+        parent_cluster: FatEntry,
+    ) -> Result<(), FsError> {
+        let chain = self.get_chain(dir_cluster)?;
+
+        if let Some((mut entry, c, i)) = self.find_in_dir(&chain, "..")? {
+            if let DirectoryEntry::Directory(inode) = &mut entry {
+                inode.start_cluster = parent_cluster;
+            }
+
+            self.write_directory_entry(c, i, &entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_ancestor(&mut self, ancestor: FatEntry, mut cluster: FatEntry) -> Result<bool, FsError> {
+        let mut visited = HashSet::new();
+
+        while visited.insert(cluster) {
+            if cluster == ancestor {
+                return Ok(true);
+            }
+
+            let chain = self.get_chain(cluster)?;
+
+            match self.find_in_dir(&chain, "..")? {
+                Some((DirectoryEntry::Directory(inode), _, _)) => cluster = inode.start_cluster,
+                _ => break,
+            }
+        }
+
+        Ok(false)
+    }
+
+    pub fn invalidate_entry(
+        &mut self,
+        chain: &Chain,
+        cluster: FatEntry,
+        idx: u32,
+    ) -> Result<(), FsError> {
+        let dir = self.read_dir(chain)?;
+
+        let pos = match dir.iter().position(|(_, c, i)| *c == cluster && *i == idx) {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+
+        let mut start = pos;
+
+        while start > 0 && matches!(dir[start - 1].0, DirectoryEntry::LongFileName(_)) {
+            start -= 1;
+        }
+
+        for (_, c, i) in &dir[start..=pos] {
+            self.write_directory_entry(*c, *i, &DirectoryEntry::Invalid)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_data(&mut self, inode: &Inode, offset: u64, size: u32) -> Result<Vec<u8>, FsError> {
+        let mut data = vec![];
+
+        if offset >= inode.length {
+            return Ok(data);
+        }
+
+        let end = min(offset + size as u64, inode.length);
+        let chain = self.get_chain(inode.start_cluster)?;
+        let cluster_size = self.io.cluster_size() as u64;
+
+        let mut pos = offset;
+
+        while pos < end {
+            let cluster = match chain.get((pos / cluster_size) as usize) {
+                Some(cluster) => *cluster,
+                None => break,
+            };
+
+            let in_cluster_offset = (pos % cluster_size) as usize;
+            let len = min(cluster_size - in_cluster_offset as u64, end - pos) as usize;
+
+            let content = self.read_cluster(cluster)?;
+            data.extend_from_slice(&content[in_cluster_offset..in_cluster_offset + len]);
+
+            pos += len as u64;
+        }
+
+        Ok(data)
+    }
+
+    pub fn write_data(
+        &mut self,
+        inode: &mut Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<u32, FsError> {
+        let mut chain = self.get_chain(inode.start_cluster)?;
+
+        let cluster_size = self.io.cluster_size() as u64;
+        let zeros = vec![0u8; cluster_size as usize];
+        let mut pos = inode.length;
+
+        while pos < offset {
+            let len = min(cluster_size - pos % cluster_size, offset - pos);
+            self.write_chain_data(&mut chain, pos, &zeros[..len as usize])?;
+            pos += len;
+        }
+
+        self.write_chain_data(&mut chain, offset, data)?;
+
+        let now = SystemTime::now();
+
+        inode.length = max(inode.length, offset + data.len() as u64);
+        inode.mtime = now;
+        inode.ctime = now;
+
+        Ok(data.len() as u32)
+    }
+
+    pub fn truncate(&mut self, inode: &mut Inode, size: u64) -> Result<(), FsError> {
+        self.transaction(|fs| fs.resize_chain(inode, size))
+    }
+
+    fn resize_chain(&mut self, inode: &mut Inode, size: u64) -> Result<(), FsError> {
+        if size > inode.length {
+            // like a write this links the new clusters right away, only the new
+            // length is journaled, so growing isn't limited by the journal size
+            self.linking = true;
+            let grown = self.write_data(inode, size, &[]);
+            self.linking = false;
+
+            return grown.map(|_| ());
+        }
+
+        let chain = self.get_chain(inode.start_cluster)?;
+        let keep = max(1, size.div_ceil(self.io.cluster_size() as u64) as usize);
+
+        if chain.len() > keep {
+            self.write_fat_entry(chain[keep - 1], EOC)?;
+
+            for cluster in &chain[keep..] {
+                self.write_fat_entry(*cluster, FRE)?;
+            }
+        }
+
+        let now = SystemTime::now();
+
+        inode.length = size;
+        inode.mtime = now;
+        inode.ctime = now;
+
+        Ok(())
+    }
+
+    fn write_chain_data(
+        &mut self,
+        chain: &mut Chain,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), FsError> {
+        let cluster_size = self.io.cluster_size() as usize;
+        let mut pos = offset;
+        let mut written = 0;
+
+        while written < data.len() {
+            let cluster_idx = (pos / cluster_size as u64) as usize;
+
+            while chain.len() <= cluster_idx {
+                self.append_to_chain(chain)?;
+            }
+
+            let in_cluster_offset = (pos % cluster_size as u64) as usize;
+            let len = min(cluster_size - in_cluster_offset, data.len() - written);
+
+            let mut content = if len == cluster_size {
+                vec![0u8; cluster_size]
+            } else {
+                self.read_cluster(chain[cluster_idx])?
+            };
+
+            content[in_cluster_offset..in_cluster_offset + len]
+                .copy_from_slice(&data[written..written + len]);
+            self.write_cluster(chain[cluster_idx], &content)?;
+
+            pos += len as u64;
+            written += len;
+        }
+
+        Ok(())
+    }
+}
+
+fn set_name(entry: &mut DirectoryEntry, name: &str) {
+    if let DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) = entry {
+        inode.name = name.to_string();
+        inode.ctime = SystemTime::now();
     }
 }
 
-impl<'a, T> BaseIO for FileSystem<'a, T>
+impl<B> BaseIO for FileSystem<B>
 where
-    T: Read + Seek + Write,
+    B: BaseIO,
 {
-    fn read_fat_entry(&mut self, cluster: FatEntry) -> FatEntry {
-        self.io.read_fat_entry(cluster)
+    fn fat_length(&self) -> FatEntry {
+        self.io.fat_length()
     }
 
-    fn write_fat_entry(&mut self, cluster: FatEntry, entry: FatEntry) {
-        self.io.write_fat_entry(cluster, entry)
+    fn cluster_size(&self) -> u32 {
+        self.io.cluster_size()
     }
 
-    fn read_cluster(&mut self, cluster: FatEntry) -> crate::Cluster {
-        self.io.read_cluster(cluster)
+    // metadata written during a transaction stays in memory until the commit,
+    // reads have to see it on top of what's on disk
+
+    fn read_fat_entry(&mut self, cluster: FatEntry) -> Result<FatEntry, FsError> {
+        match self.transaction.as_ref().and_then(|t| t.fat.get(&cluster)) {
+            Some(entry) => Ok(*entry),
+            None => self.io.read_fat_entry(cluster),
+        }
+    }
+
+    fn write_fat_entry(&mut self, cluster: FatEntry, entry: FatEntry) -> Result<(), FsError> {
+        match &mut self.transaction {
+            Some(transaction) if !self.linking => {
+                check_cluster(self.io.fat_length(), cluster)?;
+                transaction.fat.insert(cluster, entry);
+            }
+            Some(transaction) => {
+                self.io.write_fat_entry(cluster, entry)?;
+
+                // committing must not undo the write
+                if let Some(logged) = transaction.fat.get_mut(&cluster) {
+                    *logged = entry;
+                }
+            }
+            None => self.io.write_fat_entry(cluster, entry)?,
+        }
+
+        // clusters freed by a transaction can't be reused before it's committed
+        if let Some(map) = &mut self.free_map {
+            if entry != FRE || self.transaction.is_none() {
+                map.set_free(cluster, entry == FRE);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_cluster(&mut self, cluster: FatEntry) -> Result<Cluster, FsError> {
+        let mut content = self.io.read_cluster(cluster)?;
+
+        if let Some(transaction) = &self.transaction {
+            transaction.overlay_cluster(cluster, &mut content);
+        }
+
+        Ok(content)
+    }
+
+    fn write_cluster(&mut self, cluster: FatEntry, cluster_content: &[u8]) -> Result<(), FsError> {
+        self.io.write_cluster(cluster, cluster_content)?;
+
+        if let Some(transaction) = &mut self.transaction {
+            transaction.entries.retain(|(c, _), _| *c != cluster);
+        }
+
+        Ok(())
+    }
+
+    fn read_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+    ) -> Result<DirEntry, FsError> {
+        match self
+            .transaction
+            .as_ref()
+            .and_then(|t| t.entries.get(&(cluster, idx)))
+        {
+            Some(entry) => Ok(*entry),
+            None => self.io.read_raw_directory_entry(cluster, idx),
+        }
+    }
+
+    fn write_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+        entry: &DirEntry,
+    ) -> Result<(), FsError> {
+        match &mut self.transaction {
+            Some(transaction) => {
+                check_cluster(self.io.fat_length(), cluster)?;
+                transaction.entries.insert((cluster, idx), *entry);
+
+                Ok(())
+            }
+            None => self.io.write_raw_directory_entry(cluster, idx, entry),
+        }
+    }
+
+    fn read_fat(&mut self, start: FatEntry, count: u32) -> Result<Vec<FatEntry>, FsError> {
+        let mut fat = self.io.read_fat(start, count)?;
+
+        if let Some(transaction) = &self.transaction {
+            transaction.overlay_fat(start, &mut fat);
+        }
+
+        Ok(fat)
     }
 
-    fn write_cluster(&mut self, cluster: FatEntry, cluster_content: &crate::Cluster) {
-        self.io.write_cluster(cluster, cluster_content)
+    fn flush(&mut self) -> Result<(), FsError> {
+        self.io.flush()
     }
 
-    fn read_raw_directory_entry(&mut self, cluster: FatEntry, idx: u32) -> crate::DirEntry {
-        self.io.read_raw_directory_entry(cluster, idx)
+    fn sync(&mut self) -> Result<(), FsError> {
+        self.io.sync()
     }
 
-    fn write_raw_directory_entry(&mut self, cluster: FatEntry, idx: u32, entry: &crate::DirEntry) {
-        self.io.write_raw_directory_entry(cluster, idx, entry)
+    fn journal(&self) -> Option<(FatEntry, u32)> {
+        self.journal
     }
 }
@@ -0,0 +1,74 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+use fuser::{MountOption, Session, SessionUnmounter};
+
+use crate::error::FsError;
+
+use super::{basic_fs_io::BaseIO, shared_io::SharedIO, FileSystem};
+
+/// A file system mounted on a background thread. `unmount` hands the I/O layer
+/// back once the session has ended and everything was flushed.
+pub struct BackgroundMount<B>
+where
+    B: BaseIO + Send + 'static,
+{
+    mountpoint: PathBuf,
+    unmounter: SessionUnmounter,
+    session: JoinHandle<io::Result<()>>,
+    io: SharedIO<B>,
+}
+
+impl<B> BackgroundMount<B>
+where
+    B: BaseIO + Send + 'static,
+{
+    pub fn spawn(
+        fs: FileSystem<SharedIO<B>>,
+        mountpoint: &Path,
+        options: &[MountOption],
+    ) -> io::Result<Self> {
+        let io = fs.io.clone();
+
+        let mut session = Session::new(fs, mountpoint, options)?;
+        let unmounter = session.unmount_callable();
+
+        let session = thread::spawn(move || session.run());
+
+        Ok(BackgroundMount {
+            mountpoint: mountpoint.to_path_buf(),
+            unmounter,
+            session,
+            io,
+        })
+    }
+
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.session.is_finished()
+    }
+
+    pub fn unmount(mut self) -> Result<B, FsError> {
+        if !self.session.is_finished() {
+            self.unmounter.unmount()?;
+        }
+
+        match self.session.join() {
+            Ok(result) => result?,
+            Err(_) => {
+                return Err(FsError::Io(io::Error::other("mount session panicked")));
+            }
+        }
+
+        // dropping the session flushed the file system and released its handle
+        self.io
+            .try_into_inner()
+            .map_err(|_| FsError::Io(io::Error::other("device is still in use")))
+    }
+}
@@ -1,170 +1,373 @@
 use std::{
-    io::{Read, Seek, SeekFrom, Write},
-    str::from_utf8,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
 };
 
 use crate::{
-    consts::{CLUSTER_SIZE, DIR_ENTRY_SIZE, FAT_ENTRY_SIZE, FAT_START_ADDR, FS_ID, FS_VERSION},
+    consts::{
+        DIR_ENTRY_SIZE, FAT_ENTRY_SIZE, FAT_START_ADDR, FS_ID, FS_VERSION, LEGACY_CLUSTER_SIZE,
+        MIN_JOURNAL_CLUSTERS,
+    },
+    error::FsError,
     utility::{
-        fs_utility::{check_cluster, get_data_section_address},
+        fs_utility::{
+            check_cluster, check_cluster_size, entries_per_cluster, get_data_section_address,
+        },
         le_bytes_to_u32,
     },
     Cluster, DirEntry, FatEntry,
 };
 
+use super::journal::{self, Transaction};
+
 pub trait BaseIO {
-    fn read_fat_entry(&mut self, cluster: FatEntry) -> FatEntry;
-    fn write_fat_entry(&mut self, cluster: FatEntry, entry: FatEntry);
-    fn read_cluster(&mut self, cluster: FatEntry) -> Cluster;
-    fn write_cluster(&mut self, cluster: FatEntry, cluster_content: &Cluster);
-    fn read_raw_directory_entry(&mut self, cluster: FatEntry, idx: u32) -> DirEntry;
-    fn write_raw_directory_entry(&mut self, cluster: FatEntry, idx: u32, entry: &DirEntry);
+    fn fat_length(&self) -> FatEntry;
+    fn cluster_size(&self) -> u32;
+
+    fn entries_per_cluster(&self) -> u32 {
+        entries_per_cluster(self.cluster_size())
+    }
+
+    fn read_fat_entry(&mut self, cluster: FatEntry) -> Result<FatEntry, FsError>;
+    fn write_fat_entry(&mut self, cluster: FatEntry, entry: FatEntry) -> Result<(), FsError>;
+    fn read_cluster(&mut self, cluster: FatEntry) -> Result<Cluster, FsError>;
+    fn write_cluster(&mut self, cluster: FatEntry, cluster_content: &[u8]) -> Result<(), FsError>;
+    fn read_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+    ) -> Result<DirEntry, FsError>;
+    fn write_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+        entry: &DirEntry,
+    ) -> Result<(), FsError>;
+
+    fn read_fat(&mut self, start: FatEntry, count: u32) -> Result<Vec<FatEntry>, FsError> {
+        (start..start + count)
+            .map(|cluster| self.read_fat_entry(cluster))
+            .collect()
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+
+    /// Like `flush`, but only returns once everything is on stable storage.
+    fn sync(&mut self) -> Result<(), FsError> {
+        self.flush()
+    }
+
+    /// First cluster and length of the metadata journal, if the image has one.
+    fn journal(&self) -> Option<(FatEntry, u32)> {
+        None
+    }
 }
 
-pub struct FileSystemBasicIO<'a, T>
+/// What a file system lives on, usually an image file or a block device.
+pub trait Device: Read + Seek + Write {
+    /// `Write::flush` only hands data to the OS, this waits until it's stored.
+    fn sync_data(&mut self) -> io::Result<()>;
+}
+
+impl Device for File {
+    fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+impl<T> Device for Cursor<T>
 where
-    T: Read + Seek + Write,
+    Cursor<T>: Read + Seek + Write,
 {
-    pub device: &'a mut T,
+    fn sync_data(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<D> Device for &mut D
+where
+    D: Device + ?Sized,
+{
+    fn sync_data(&mut self) -> io::Result<()> {
+        (**self).sync_data()
+    }
+}
+
+impl<D> Device for Box<D>
+where
+    D: Device + ?Sized,
+{
+    fn sync_data(&mut self) -> io::Result<()> {
+        (**self).sync_data()
+    }
+}
+
+pub struct FileSystemBasicIO<T>
+where
+    T: Device,
+{
+    pub device: T,
     pub fat_length: FatEntry,
+    pub cluster_size: u32,
     pub start_data_region: u64,
+    pub journal: Option<(FatEntry, u32)>,
+    /// Transaction left in the journal of an image opened with `open_read_only`,
+    /// laid over everything read.
+    pub pending: Option<Transaction>,
 }
-impl<'a, T> FileSystemBasicIO<'a, T>
+impl<T> FileSystemBasicIO<T>
 where
-    T: Read + Seek + Write,
+    T: Device,
 {
-    pub fn open_file_system(device: &'a mut T) -> Self
-    where
-        T: Read + Write + Seek,
-    {
-        device.rewind().unwrap();
+    pub fn open_file_system(device: T) -> Result<Self, FsError> {
+        let mut io = Self::open(device)?;
+
+        // finish or throw away whatever was in flight when the image was last written
+        if let Some((start, length)) = io.journal {
+            journal::replay(&mut io, start, length)?;
+        }
 
-        let mut fat_prelude_buffer = [0u8; 14];
+        Ok(io)
+    }
 
-        device.read_exact(&mut fat_prelude_buffer).unwrap();
+    /// Opens an image without writing to it. A transaction left in the journal
+    /// isn't replayed but kept in memory, so reads see the image as replaying
+    /// would leave it.
+    pub fn open_read_only(device: T) -> Result<Self, FsError> {
+        let mut io = Self::open(device)?;
 
-        if fat_prelude_buffer[0..=8] != FS_ID {
-            panic!(
-                "invalid filesystem: {}",
-                from_utf8(&fat_prelude_buffer[0..=8]).unwrap()
-            );
+        if let Some((start, length)) = io.journal {
+            io.pending = journal::pending(&mut io, start, length)?;
         }
 
-        if fat_prelude_buffer[9..10] != FS_VERSION {
-            panic!("invalid filesystem version {}", fat_prelude_buffer[9]);
+        Ok(io)
+    }
+
+    fn open(mut device: T) -> Result<Self, FsError> {
+        device.rewind()?;
+
+        let mut fat_prelude_buffer = [0u8; 26];
+
+        device.read_exact(&mut fat_prelude_buffer)?;
+
+        if fat_prelude_buffer[0..=8] != FS_ID {
+            let mut magic = [0u8; 9];
+            magic.copy_from_slice(&fat_prelude_buffer[0..=8]);
+
+            return Err(FsError::BadMagic(magic));
         }
 
+        let (cluster_size, journal_start, journal_length) = match fat_prelude_buffer[9] {
+            1 => (LEGACY_CLUSTER_SIZE, 0, 0),
+            2 => (le_bytes_to_u32(&fat_prelude_buffer[14..18]), 0, 0),
+            v if [v] == FS_VERSION => (
+                le_bytes_to_u32(&fat_prelude_buffer[14..18]),
+                le_bytes_to_u32(&fat_prelude_buffer[18..22]),
+                le_bytes_to_u32(&fat_prelude_buffer[22..26]),
+            ),
+            v => return Err(FsError::BadVersion(v)),
+        };
+
+        check_cluster_size(cluster_size)?;
+
         let fat_length = le_bytes_to_u32(&fat_prelude_buffer[10..14]);
 
+        let journal = match (journal_start, journal_length) {
+            (0, 0) => None,
+            (start, length)
+                if start > 1
+                    && length >= MIN_JOURNAL_CLUSTERS
+                    && start as u64 + length as u64 - 1 <= fat_length as u64 =>
+            {
+                Some((start, length))
+            }
+            (start, length) => return Err(FsError::BadJournal(start, length)),
+        };
+
         let start_data_region = get_data_section_address(fat_length);
 
-        FileSystemBasicIO {
+        Ok(FileSystemBasicIO {
             device,
             fat_length,
+            cluster_size,
             start_data_region,
-        }
+            journal,
+            pending: None,
+        })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    fn cluster_address(&self, cluster: FatEntry) -> u64 {
+        self.start_data_region + (cluster - 1) as u64 * self.cluster_size as u64
     }
 }
 
-impl<'a, T> BaseIO for FileSystemBasicIO<'a, T>
+impl<T> BaseIO for FileSystemBasicIO<T>
 where
-    T: Read + Seek + Write,
+    T: Device,
 {
-    fn read_fat_entry(&mut self, cluster: FatEntry) -> FatEntry {
-        if let Err(s) = check_cluster(self.fat_length, cluster) {
-            panic!("{}", s)
+    fn fat_length(&self) -> FatEntry {
+        self.fat_length
+    }
+
+    fn cluster_size(&self) -> u32 {
+        self.cluster_size
+    }
+
+    fn read_fat_entry(&mut self, cluster: FatEntry) -> Result<FatEntry, FsError> {
+        check_cluster(self.fat_length, cluster)?;
+
+        if let Some(entry) = self.pending.as_ref().and_then(|t| t.fat.get(&cluster)) {
+            return Ok(*entry);
         }
 
-        let addr = (cluster - 1) * FAT_ENTRY_SIZE;
+        let addr = (cluster - 1) as u64 * FAT_ENTRY_SIZE as u64;
 
         let mut buf = [0u8; 4];
 
-        self.device
-            .seek(SeekFrom::Start(FAT_START_ADDR + addr as u64))
-            .unwrap();
+        self.device.seek(SeekFrom::Start(FAT_START_ADDR + addr))?;
 
-        self.device.read_exact(&mut buf).unwrap();
+        self.device.read_exact(&mut buf)?;
 
-        le_bytes_to_u32(&buf)
+        Ok(le_bytes_to_u32(&buf))
     }
 
-    fn write_fat_entry(&mut self, cluster: FatEntry, entry: FatEntry) {
-        if let Err(s) = check_cluster(self.fat_length, cluster) {
-            panic!("{}", s)
-        }
+    fn write_fat_entry(&mut self, cluster: FatEntry, entry: FatEntry) -> Result<(), FsError> {
+        check_cluster(self.fat_length, cluster)?;
 
-        let addr = (cluster - 1) * FAT_ENTRY_SIZE;
+        let addr = (cluster - 1) as u64 * FAT_ENTRY_SIZE as u64;
 
-        self.device
-            .seek(SeekFrom::Start(FAT_START_ADDR + addr as u64))
-            .unwrap();
+        self.device.seek(SeekFrom::Start(FAT_START_ADDR + addr))?;
 
-        self.device.write_all(&entry.to_le_bytes()).unwrap();
+        self.device.write_all(&entry.to_le_bytes())?;
+
+        Ok(())
     }
 
-    fn read_cluster(&mut self, cluster: FatEntry) -> Cluster {
-        let mut cluster_content = [0u8; CLUSTER_SIZE as usize];
+    fn read_cluster(&mut self, cluster: FatEntry) -> Result<Cluster, FsError> {
+        let mut cluster_content = vec![0u8; self.cluster_size as usize];
 
-        if let Err(s) = check_cluster(self.fat_length, cluster) {
-            panic!("{}", s)
-        }
+        check_cluster(self.fat_length, cluster)?;
 
-        let addr = ((cluster - 1) * CLUSTER_SIZE) as u64;
+        let addr = self.cluster_address(cluster);
 
-        self.device
-            .seek(SeekFrom::Start(self.start_data_region + addr))
-            .unwrap();
+        self.device.seek(SeekFrom::Start(addr))?;
 
-        self.device.read_exact(&mut cluster_content).unwrap();
+        self.device.read_exact(&mut cluster_content)?;
 
-        cluster_content
+        if let Some(transaction) = &self.pending {
+            transaction.overlay_cluster(cluster, &mut cluster_content);
+        }
+
+        Ok(cluster_content)
     }
 
-    fn write_cluster(&mut self, cluster: FatEntry, cluster_content: &Cluster) {
-        if let Err(s) = check_cluster(self.fat_length, cluster) {
-            panic!("{}", s)
+    fn write_cluster(&mut self, cluster: FatEntry, cluster_content: &[u8]) -> Result<(), FsError> {
+        check_cluster(self.fat_length, cluster)?;
+
+        if cluster_content.len() != self.cluster_size as usize {
+            return Err(FsError::BadClusterSize(cluster_content.len() as u32));
         }
 
-        let addr = ((cluster - 1) * CLUSTER_SIZE) as u64;
+        let addr = self.cluster_address(cluster);
 
-        self.device
-            .seek(SeekFrom::Start(self.start_data_region + addr))
-            .unwrap();
+        self.device.seek(SeekFrom::Start(addr))?;
+
+        self.device.write_all(cluster_content)?;
 
-        self.device.write_all(cluster_content).unwrap();
+        Ok(())
     }
 
-    fn read_raw_directory_entry(&mut self, cluster: FatEntry, idx: u32) -> DirEntry {
-        if let Err(s) = check_cluster(self.fat_length, cluster) {
-            panic!("{}", s)
+    fn read_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+    ) -> Result<DirEntry, FsError> {
+        check_cluster(self.fat_length, cluster)?;
+
+        if let Some(entry) = self
+            .pending
+            .as_ref()
+            .and_then(|t| t.entries.get(&(cluster, idx)))
+        {
+            return Ok(*entry);
         }
 
-        let addr = ((cluster - 1) * CLUSTER_SIZE) as u64;
+        let addr = self.cluster_address(cluster);
         let offset = (idx * DIR_ENTRY_SIZE) as u64;
 
         let mut buf = [0u8; DIR_ENTRY_SIZE as usize];
 
-        self.device
-            .seek(SeekFrom::Start(self.start_data_region + addr + offset))
-            .unwrap();
+        self.device.seek(SeekFrom::Start(addr + offset))?;
 
-        self.device.read_exact(&mut buf).unwrap();
+        self.device.read_exact(&mut buf)?;
 
-        buf
+        Ok(buf)
     }
 
-    fn write_raw_directory_entry(&mut self, cluster: FatEntry, idx: u32, entry: &DirEntry) {
-        if let Err(s) = check_cluster(self.fat_length, cluster) {
-            panic!("{}", s)
-        }
+    fn write_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+        entry: &DirEntry,
+    ) -> Result<(), FsError> {
+        check_cluster(self.fat_length, cluster)?;
 
-        let addr = ((cluster - 1) * CLUSTER_SIZE) as u64;
+        let addr = self.cluster_address(cluster);
         let offset = (idx * DIR_ENTRY_SIZE) as u64;
 
-        self.device
-            .seek(SeekFrom::Start(self.start_data_region + addr + offset))
-            .unwrap();
+        self.device.seek(SeekFrom::Start(addr + offset))?;
+
+        self.device.write_all(entry)?;
+
+        Ok(())
+    }
+
+    fn read_fat(&mut self, start: FatEntry, count: u32) -> Result<Vec<FatEntry>, FsError> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        check_cluster(self.fat_length, start)?;
+        check_cluster(self.fat_length, start + count - 1)?;
+
+        let addr = (start - 1) as u64 * FAT_ENTRY_SIZE as u64;
+        let mut buf = vec![0u8; count as usize * FAT_ENTRY_SIZE as usize];
+
+        self.device.seek(SeekFrom::Start(FAT_START_ADDR + addr))?;
+        self.device.read_exact(&mut buf)?;
+
+        let mut fat: Vec<FatEntry> = buf
+            .chunks_exact(FAT_ENTRY_SIZE as usize)
+            .map(le_bytes_to_u32)
+            .collect();
+
+        if let Some(transaction) = &self.pending {
+            transaction.overlay_fat(start, &mut fat);
+        }
+
+        Ok(fat)
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        self.device.flush()?;
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), FsError> {
+        self.device.flush()?;
+        self.device.sync_data()?;
+
+        Ok(())
+    }
 
-        self.device.write_all(entry).unwrap();
+    fn journal(&self) -> Option<(FatEntry, u32)> {
+        self.journal
     }
 }
@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    consts::DIR_ENTRY_SIZE, error::FsError, utility::fs_utility::check_cluster, Cluster, DirEntry,
+    FatEntry,
+};
+
+use super::basic_fs_io::BaseIO;
+
+pub const DEFAULT_CACHE_SIZE: usize = 256;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Page {
+    data: Cluster,
+    dirty: bool,
+    used: u64,
+}
+
+/// Write-back cache in front of another `BaseIO`. Keeps the whole FAT and the
+/// `capacity` most recently used clusters in memory until they are flushed.
+pub struct CachedIO<B>
+where
+    B: BaseIO,
+{
+    // only `None` once `into_inner` took it
+    inner: Option<B>,
+    fat: Vec<FatEntry>,
+    dirty_fat: BTreeSet<FatEntry>,
+    pages: HashMap<FatEntry, Page>,
+    lru: BTreeMap<u64, FatEntry>,
+    capacity: usize,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl<B> CachedIO<B>
+where
+    B: BaseIO,
+{
+    pub fn new(mut inner: B, capacity: usize) -> Result<Self, FsError> {
+        let fat = inner.read_fat(1, inner.fat_length())?;
+
+        Ok(CachedIO {
+            inner: Some(inner),
+            fat,
+            dirty_fat: BTreeSet::new(),
+            pages: HashMap::new(),
+            lru: BTreeMap::new(),
+            capacity: capacity.max(1),
+            clock: 0,
+            stats: CacheStats::default(),
+        })
+    }
+
+    pub fn get_ref(&self) -> &B {
+        self.inner.as_ref().expect("inner taken")
+    }
+
+    fn get_mut(&mut self) -> &mut B {
+        self.inner.as_mut().expect("inner taken")
+    }
+
+    pub fn into_inner(mut self) -> Result<B, FsError> {
+        self.flush()?;
+
+        Ok(self.inner.take().expect("inner taken"))
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn dirty(&self) -> usize {
+        self.dirty_fat.len() + self.pages.values().filter(|p| p.dirty).count()
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn evict(&mut self) -> Result<(), FsError> {
+        while self.pages.len() >= self.capacity {
+            let (used, cluster) = match self.lru.first_key_value() {
+                Some((used, cluster)) => (*used, *cluster),
+                None => break,
+            };
+
+            // a page whose write-back failed stays cached, dropping it would lose its data
+            if let Some(page) = self.pages.get(&cluster).filter(|page| page.dirty) {
+                let inner = self.inner.as_mut().expect("inner taken");
+                inner.write_cluster(cluster, &page.data)?;
+            }
+
+            self.lru.remove(&used);
+            self.pages.remove(&cluster);
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, cluster: FatEntry, data: Cluster, dirty: bool) -> Result<(), FsError> {
+        self.evict()?;
+
+        let used = self.tick();
+
+        self.lru.insert(used, cluster);
+        self.pages.insert(cluster, Page { data, dirty, used });
+
+        Ok(())
+    }
+
+    fn page(&mut self, cluster: FatEntry) -> Result<&mut Page, FsError> {
+        check_cluster(self.get_ref().fat_length(), cluster)?;
+
+        if self.pages.contains_key(&cluster) {
+            self.stats.hits += 1;
+
+            let used = self.tick();
+            let page = self.pages.get_mut(&cluster).unwrap();
+
+            self.lru.remove(&page.used);
+            self.lru.insert(used, cluster);
+            page.used = used;
+        } else {
+            self.stats.misses += 1;
+
+            let data = self.get_mut().read_cluster(cluster)?;
+            self.insert(cluster, data, false)?;
+        }
+
+        Ok(self.pages.get_mut(&cluster).unwrap())
+    }
+}
+
+impl<B> BaseIO for CachedIO<B>
+where
+    B: BaseIO,
+{
+    fn fat_length(&self) -> FatEntry {
+        self.get_ref().fat_length()
+    }
+
+    fn cluster_size(&self) -> u32 {
+        self.get_ref().cluster_size()
+    }
+
+    fn read_fat_entry(&mut self, cluster: FatEntry) -> Result<FatEntry, FsError> {
+        check_cluster(self.fat_length(), cluster)?;
+
+        Ok(self.fat[(cluster - 1) as usize])
+    }
+
+    fn write_fat_entry(&mut self, cluster: FatEntry, entry: FatEntry) -> Result<(), FsError> {
+        check_cluster(self.fat_length(), cluster)?;
+
+        self.fat[(cluster - 1) as usize] = entry;
+        self.dirty_fat.insert(cluster);
+
+        Ok(())
+    }
+
+    fn read_cluster(&mut self, cluster: FatEntry) -> Result<Cluster, FsError> {
+        Ok(self.page(cluster)?.data.clone())
+    }
+
+    fn write_cluster(&mut self, cluster: FatEntry, cluster_content: &[u8]) -> Result<(), FsError> {
+        check_cluster(self.fat_length(), cluster)?;
+
+        if cluster_content.len() != self.cluster_size() as usize {
+            return Err(FsError::BadClusterSize(cluster_content.len() as u32));
+        }
+
+        match self.pages.get_mut(&cluster) {
+            Some(page) => {
+                page.data.copy_from_slice(cluster_content);
+                page.dirty = true;
+            }
+            None => self.insert(cluster, cluster_content.to_vec(), true)?,
+        }
+
+        Ok(())
+    }
+
+    fn read_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+    ) -> Result<DirEntry, FsError> {
+        let offset = (idx * DIR_ENTRY_SIZE) as usize;
+        let page = self.page(cluster)?;
+
+        let mut entry = [0u8; DIR_ENTRY_SIZE as usize];
+        entry.copy_from_slice(&page.data[offset..offset + DIR_ENTRY_SIZE as usize]);
+
+        Ok(entry)
+    }
+
+    fn write_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+        entry: &DirEntry,
+    ) -> Result<(), FsError> {
+        let offset = (idx * DIR_ENTRY_SIZE) as usize;
+        let page = self.page(cluster)?;
+
+        page.data[offset..offset + DIR_ENTRY_SIZE as usize].copy_from_slice(entry);
+        page.dirty = true;
+
+        Ok(())
+    }
+
+    fn read_fat(&mut self, start: FatEntry, count: u32) -> Result<Vec<FatEntry>, FsError> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        check_cluster(self.fat_length(), start)?;
+        check_cluster(self.fat_length(), start + count - 1)?;
+
+        Ok(self.fat[(start - 1) as usize..(start - 1 + count) as usize].to_vec())
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        let inner = self.inner.as_mut().expect("inner taken");
+
+        while let Some(cluster) = self.dirty_fat.pop_first() {
+            if let Err(e) = inner.write_fat_entry(cluster, self.fat[(cluster - 1) as usize]) {
+                self.dirty_fat.insert(cluster);
+                return Err(e);
+            }
+        }
+
+        for (cluster, page) in self.pages.iter_mut().filter(|(_, page)| page.dirty) {
+            inner.write_cluster(*cluster, &page.data)?;
+            page.dirty = false;
+        }
+
+        inner.flush()
+    }
+
+    fn sync(&mut self) -> Result<(), FsError> {
+        self.flush()?;
+        self.get_mut().sync()
+    }
+
+    fn journal(&self) -> Option<(FatEntry, u32)> {
+        self.get_ref().journal()
+    }
+}
+
+impl<B> Drop for CachedIO<B>
+where
+    B: BaseIO,
+{
+    fn drop(&mut self) {
+        if self.inner.is_none() {
+            return;
+        }
+
+        // nobody is left to hand the error to
+        if let Err(e) = self.flush() {
+            eprintln!("naths_fat_fs: writing back the cache failed: {}", e);
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use std::{
+    cmp::min,
     str::from_utf8,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    consts::DIR_ENTRY_SIZE,
+    consts::{DIR_ENTRY_SIZE, INODE_NAME_SIZE, LONG_NAME_SIZE},
+    error::FsError,
     utility::{le_bytes_to_u32, le_bytes_to_u64_padded},
     DirEntry, FatEntry,
 };
@@ -21,41 +23,63 @@ pub enum DirectoryEntry {
 
 impl DirectoryEntry {
     pub fn split(&self) -> Vec<DirEntry> {
-        todo!()
+        let mut vec = vec![];
 
-        // let mut vec = vec![];
+        let inode = match self {
+            DirectoryEntry::Directory(i) => i,
+            DirectoryEntry::File(i) => i,
+            _ => return vec![DirEntry::from(self)],
+        };
 
-        // let inode = match self {
-        //     DirectoryEntry::Invalid => None,
-        //     DirectoryEntry::LongFileName(_) => None,
-        //     DirectoryEntry::Directory(i) => Some(i),
-        //     DirectoryEntry::File(i) => Some(i),
-        // };
+        let (mut head, _) = split_name(&inode.name);
 
-        // match inode {
-        //     Some(inode) => {
-        //         todo!()
-        //     }
-        //     None => (),
-        // };
+        while !head.is_empty() {
+            let mut end = min(head.len(), LONG_NAME_SIZE as usize);
 
-        // vec
+            while !head.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            let (chunk, rest) = head.split_at(end);
+
+            vec.push(DirEntry::from(&DirectoryEntry::LongFileName(
+                chunk.to_string(),
+            )));
+
+            head = rest;
+        }
+
+        vec.push(DirEntry::from(self));
+
+        vec
+    }
+}
+
+fn split_name(name: &str) -> (&str, &str) {
+    let mut tail_start = name.len().saturating_sub(INODE_NAME_SIZE as usize);
+
+    while !name.is_char_boundary(tail_start) {
+        tail_start += 1;
     }
+
+    name.split_at(tail_start)
 }
 
-impl From<&DirEntry> for DirectoryEntry {
-    fn from(raw_entry: &DirEntry) -> Self {
+impl TryFrom<&DirEntry> for DirectoryEntry {
+    type Error = FsError;
+
+    fn try_from(raw_entry: &DirEntry) -> Result<Self, Self::Error> {
         let type_indicator = le_bytes_to_u32(raw_entry);
 
-        if (0b1 << 0) & type_indicator == 0 {
+        Ok(if (0b1 << 0) & type_indicator == 0 {
             DirectoryEntry::Invalid
         } else if (0b1 << 1) & type_indicator != 0 {
-            DirectoryEntry::LongFileName(from_utf8(&raw_entry[1..]).unwrap().to_owned())
+            DirectoryEntry::LongFileName(from_utf8(&raw_entry[1..])?.trim_matches('\0').to_owned())
         } else if (0b1 << 2) & type_indicator != 0 {
-            DirectoryEntry::Directory(Inode::from(raw_entry))
+            DirectoryEntry::Directory(Inode::try_from(raw_entry)?)
         } else {
-            DirectoryEntry::File(Inode::from(raw_entry))
-        }
+            DirectoryEntry::File(Inode::try_from(raw_entry)?)
+        })
     }
 }
 
@@ -86,7 +110,7 @@ impl From<&DirectoryEntry> for DirEntry {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Inode {
     pub name: String,
     pub length: u64,
@@ -141,7 +165,7 @@ impl From<&Inode> for DirEntry {
             &value
                 .ctime
                 .duration_since(UNIX_EPOCH)
-                .unwrap()
+                .unwrap_or_default()
                 .as_millis()
                 .to_le_bytes()[0..6],
         );
@@ -150,7 +174,7 @@ impl From<&Inode> for DirEntry {
             &value
                 .mtime
                 .duration_since(UNIX_EPOCH)
-                .unwrap()
+                .unwrap_or_default()
                 .as_millis()
                 .to_le_bytes()[0..6],
         );
@@ -159,7 +183,7 @@ impl From<&Inode> for DirEntry {
             &value
                 .atime
                 .duration_since(UNIX_EPOCH)
-                .unwrap()
+                .unwrap_or_default()
                 .as_millis()
                 .to_le_bytes()[0..6],
         );
@@ -167,15 +191,17 @@ impl From<&Inode> for DirEntry {
         raw[29..33].copy_from_slice(&value.start_cluster.to_le_bytes());
         raw[33..39].copy_from_slice(&(value.length.to_le_bytes()[0..6]));
 
-        let name_bytes = value.name.as_bytes();
+        let name_bytes = split_name(&value.name).1.as_bytes();
         raw[39..39 + name_bytes.len()].copy_from_slice(name_bytes);
 
         raw
     }
 }
 
-impl From<&DirEntry> for Inode {
-    fn from(value: &DirEntry) -> Self {
+impl TryFrom<&DirEntry> for Inode {
+    type Error = FsError;
+
+    fn try_from(value: &DirEntry) -> Result<Self, Self::Error> {
         let number_of_hlinks = (value[0] & 0xF0) >> 4;
         let uid = le_bytes_to_u64_padded(&value[1..5]) as u32;
         let gid = le_bytes_to_u64_padded(&value[5..9]) as u32;
@@ -185,12 +211,9 @@ impl From<&DirEntry> for Inode {
         let permission = le_bytes_to_u64_padded(&value[27..29]) as u16;
         let start_cluster = le_bytes_to_u64_padded(&value[29..33]) as FatEntry;
         let length = le_bytes_to_u64_padded(&value[33..39]);
-        let name = from_utf8(&value[39..64])
-            .unwrap()
-            .trim_matches('\0')
-            .to_owned();
+        let name = from_utf8(&value[39..64])?.trim_matches('\0').to_owned();
 
-        Inode {
+        Ok(Inode {
             name,
             length,
             uid,
@@ -201,6 +224,6 @@ impl From<&DirEntry> for Inode {
             atime,
             number_of_hlinks,
             start_cluster,
-        }
+        })
     }
 }
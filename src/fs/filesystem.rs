@@ -1,120 +1,355 @@
-use fuser::{FileAttr, FileType, Filesystem};
-use libc::{EBADFD, ENOENT};
+use fuser::{FileAttr, FileType, Filesystem, TimeOrNow};
+use libc::{c_int, EBADFD, EINVAL, EISDIR, ENOENT, EPERM, S_IFMT, S_IFREG};
 use std::{
     ffi::OsString,
-    io::{Read, Seek, Write},
-    time::{Duration, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{
-    consts::CLUSTER_SIZE,
-    utility::fs_utility::{from_inode, to_inode},
-};
+use crate::{consts::NAME_MAX, Dir};
 
 use super::{basic_fs_io::BaseIO, directory::DirectoryEntry, FileSystem};
 
-impl<'a, T> Filesystem for FileSystem<'a, T>
+const TTL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AttrOverrides {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub umask: Option<u16>,
+}
+
+fn file_attr(ino: u64, entry: &DirectoryEntry, overrides: &AttrOverrides) -> Option<FileAttr> {
+    let (kind, inode) = match entry {
+        DirectoryEntry::Invalid => return None,
+        DirectoryEntry::LongFileName(_) => return None,
+        DirectoryEntry::Directory(i) => (FileType::Directory, i),
+        DirectoryEntry::File(i) => (FileType::RegularFile, i),
+    };
+
+    Some(FileAttr {
+        ino,
+        size: inode.length,
+        blocks: 0,
+        atime: inode.atime,
+        mtime: inode.mtime,
+        ctime: inode.ctime,
+        crtime: UNIX_EPOCH,
+        kind,
+        perm: inode.permission & !overrides.umask.unwrap_or(0),
+        nlink: 1,
+        uid: overrides.uid.unwrap_or(inode.uid),
+        gid: overrides.gid.unwrap_or(inode.gid),
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    })
+}
+
+impl<B> FileSystem<B>
 where
-    T: Read + Seek + Write,
+    B: BaseIO,
 {
-    fn lookup(
+    fn create_attr(
         &mut self,
-        _req: &fuser::Request<'_>,
+        req: &fuser::Request<'_>,
         parent: u64,
         name: &std::ffi::OsStr,
-        reply: fuser::ReplyEntry,
-    ) {
-        let (c, i) = from_inode(parent);
-        let parent_dir = DirectoryEntry::from(&self.read_raw_directory_entry(c, i));
-
-        let chain;
-
-        match parent_dir {
-            DirectoryEntry::Invalid => reply.error(EBADFD),
-            DirectoryEntry::LongFileName(_) => reply.error(EBADFD),
-            DirectoryEntry::Directory(inode) => {
-                chain = self.get_chain(inode.start_cluster);
-
-                let dir = self.read_dir(&chain);
-
-                for (e, c, i) in &dir {
-                    let (t, inode) = match e {
-                        DirectoryEntry::Invalid => continue,
-                        DirectoryEntry::LongFileName(_) => continue,
-                        DirectoryEntry::Directory(i) => (FileType::Directory, i),
-                        DirectoryEntry::File(i) => (FileType::RegularFile, i),
-                    };
-
-                    if name.to_str().unwrap() == inode.name {
-                        reply.entry(
-                            &Duration::from_secs(10),
-                            &FileAttr {
-                                ino: to_inode(*c, *i),
-                                size: inode.length,
-                                blocks: 0,
-                                atime: inode.atime,
-                                mtime: inode.mtime,
-                                ctime: inode.ctime,
-                                crtime: UNIX_EPOCH,
-                                kind: t,
-                                perm: inode.permission,
-                                nlink: 1,
-                                uid: inode.uid,
-                                gid: inode.gid,
-                                rdev: 0,
-                                blksize: 0,
-                                flags: 0,
-                            },
-                            0,
-                        );
-                        return;
+        mode: u32,
+        umask: u32,
+        directory: bool,
+    ) -> Result<FileAttr, c_int> {
+        let name = name.to_str().ok_or(EINVAL)?;
+        let (c, i) = self.inodes.resolve(parent);
+        let permission = (mode & !umask & 0o7777) as u16;
+
+        let (c, i) = if directory {
+            self.create_dir(c, i, name, req.uid(), req.gid(), permission)?
+        } else {
+            self.create_file(c, i, name, req.uid(), req.gid(), permission)?
+        };
+
+        let entry = self.read_directory_entry(c, i)?;
+
+        file_attr(self.inodes.inode(c, i), &entry, &self.overrides).ok_or(EBADFD)
+    }
+
+    fn lookup_attr(&mut self, parent: u64, name: &std::ffi::OsStr) -> Result<FileAttr, c_int> {
+        let (c, i) = self.inodes.resolve(parent);
+        let chain = self.dir_chain(c, i)?;
+
+        let (e, c, i) = self
+            .find_in_dir(&chain, name.to_str().ok_or(EINVAL)?)?
+            .ok_or(ENOENT)?;
+
+        file_attr(self.inodes.inode(c, i), &e, &self.overrides).ok_or(EBADFD)
+    }
+
+    fn attr(&mut self, ino: u64) -> Result<FileAttr, c_int> {
+        let (cluster, idx) = self.inodes.resolve(ino);
+
+        let dir = self.read_directory_entry(cluster, idx)?;
+
+        file_attr(ino, &dir, &self.overrides).ok_or(EBADFD)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn set_attr(
+        &mut self,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+    ) -> Result<FileAttr, c_int> {
+        // the new size and the shortened chain have to land together
+        self.transaction(|fs| {
+            let (cluster, idx) = fs.inodes.resolve(ino);
+            let mut dir = fs.read_directory_entry(cluster, idx)?;
+
+            let inode = match &mut dir {
+                DirectoryEntry::File(inode) => {
+                    if let Some(size) = size {
+                        fs.truncate(inode, size)?;
+                    }
+
+                    inode
+                }
+                DirectoryEntry::Directory(inode) => {
+                    if size.is_some() {
+                        return Err(EISDIR);
                     }
+
+                    inode
                 }
+                _ => return Err(ENOENT),
+            };
+
+            let now = SystemTime::now();
 
-                reply.error(ENOENT);
+            if let Some(mode) = mode {
+                inode.permission = (mode & 0o7777) as u16;
             }
-            DirectoryEntry::File(_) => reply.error(EBADFD),
-        }
-    }
 
-    fn getattr(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyAttr) {
-        let (cluster, idx) = from_inode(ino);
+            if let Some(uid) = uid {
+                inode.uid = uid;
+            }
 
-        let dir = DirectoryEntry::from(&self.read_raw_directory_entry(cluster, idx));
+            if let Some(gid) = gid {
+                inode.gid = gid;
+            }
 
-        let (t, inode) = match dir {
-            DirectoryEntry::Invalid => {
-                reply.error(EBADFD);
-                return;
+            if let Some(atime) = atime {
+                inode.atime = match atime {
+                    TimeOrNow::SpecificTime(time) => time,
+                    TimeOrNow::Now => now,
+                };
             }
-            DirectoryEntry::LongFileName(_) => {
-                reply.error(EBADFD);
-                return;
+
+            if let Some(mtime) = mtime {
+                inode.mtime = match mtime {
+                    TimeOrNow::SpecificTime(time) => time,
+                    TimeOrNow::Now => now,
+                };
             }
-            DirectoryEntry::Directory(i) => (FileType::Directory, i),
-            DirectoryEntry::File(i) => (FileType::RegularFile, i),
+
+            inode.ctime = ctime.unwrap_or(now);
+
+            fs.write_directory_entry(cluster, idx, &dir)?;
+
+            file_attr(ino, &dir, &fs.overrides).ok_or(EBADFD)
+        })
+    }
+
+    fn read_ino(&mut self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, c_int> {
+        let (cluster, idx) = self.inodes.resolve(ino);
+
+        match self.read_directory_entry(cluster, idx)? {
+            DirectoryEntry::File(i) => Ok(self.read_data(&i, offset as u64, size)?),
+            _ => Err(ENOENT),
+        }
+    }
+
+    fn write_ino(&mut self, ino: u64, offset: i64, data: &[u8]) -> Result<u32, c_int> {
+        let (cluster, idx) = self.inodes.resolve(ino);
+        let mut dir = self.read_directory_entry(cluster, idx)?;
+
+        let written = match &mut dir {
+            DirectoryEntry::File(i) => self.write_data(i, offset as u64, data)?,
+            _ => return Err(ENOENT),
         };
 
-        reply.attr(
-            &Duration::from_secs(10),
-            &FileAttr {
-                ino,
-                size: inode.length,
-                blocks: 0,
-                atime: inode.atime,
-                mtime: inode.mtime,
-                ctime: inode.ctime,
-                crtime: UNIX_EPOCH,
-                kind: t,
-                perm: inode.permission,
-                nlink: 1,
-                uid: inode.uid,
-                gid: inode.gid,
-                rdev: 0,
-                blksize: 0,
-                flags: 0,
+        self.write_directory_entry(cluster, idx, &dir)?;
+
+        Ok(written)
+    }
+
+    fn read_ino_dir(&mut self, ino: u64) -> Result<Dir, c_int> {
+        let (c, i) = self.inodes.resolve(ino);
+        let chain = self.dir_chain(c, i)?;
+
+        Ok(self.read_dir(&chain)?)
+    }
+}
+
+impl<B> Filesystem for FileSystem<B>
+where
+    B: BaseIO,
+{
+    fn lookup(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEntry,
+    ) {
+        match self.lookup_attr(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn getattr(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyAttr) {
+        match self.attr(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: fuser::ReplyAttr,
+    ) {
+        match self.set_attr(ino, mode, uid, gid, size, atime, mtime, ctime) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn mknod(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        umask: u32,
+        _rdev: u32,
+        reply: fuser::ReplyEntry,
+    ) {
+        if mode & S_IFMT != S_IFREG {
+            reply.error(EPERM);
+            return;
+        }
+
+        match self.create_attr(req, parent, name, mode, umask, false) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn create(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        umask: u32,
+        _flags: i32,
+        reply: fuser::ReplyCreate,
+    ) {
+        match self.create_attr(req, parent, name, mode, umask, false) {
+            Ok(attr) => reply.created(&TTL, &attr, 0, 0, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        umask: u32,
+        reply: fuser::ReplyEntry,
+    ) {
+        match self.create_attr(req, parent, name, mode, umask, true) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rmdir(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let (c, i) = self.inodes.resolve(parent);
+
+        match name.to_str() {
+            Some(name) => match self.remove_dir(c, i, name) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
             },
-        );
+            None => reply.error(EINVAL),
+        }
+    }
+
+    fn unlink(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let (c, i) = self.inodes.resolve(parent);
+
+        match name.to_str() {
+            Some(name) => match self.remove_file(c, i, name) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            },
+            None => reply.error(EINVAL),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        parent: u64,
+        name: &std::ffi::OsStr,
+        newparent: u64,
+        newname: &std::ffi::OsStr,
+        flags: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let (c, i) = self.inodes.resolve(parent);
+        let (nc, ni) = self.inodes.resolve(newparent);
+
+        match (name.to_str(), newname.to_str()) {
+            (Some(name), Some(newname)) => match self.rename(c, i, name, nc, ni, newname, flags) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            },
+            _ => reply.error(EINVAL),
+        }
     }
 
     fn open(&mut self, _req: &fuser::Request<'_>, _ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
@@ -127,29 +362,33 @@ where
         ino: u64,
         _fh: u64,
         offset: i64,
-        _size: u32,
+        size: u32,
         _flags: i32,
         _lock_owner: Option<u64>,
         reply: fuser::ReplyData,
     ) {
-        let (cluster, idx) = from_inode(ino);
-        let dir = DirectoryEntry::from(&self.read_raw_directory_entry(cluster, idx));
-
-        match &dir {
-            DirectoryEntry::File(i) => {
-                let start = i.start_cluster;
-                let _length = i.length;
-                let _chain = self.get_chain(start);
-
-                let _cluster = offset as u32 / CLUSTER_SIZE;
-                let _in_cluster_offset = offset as u32 % CLUSTER_SIZE;
-
-                // TODO
+        match self.read_ino(ino, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e),
+        }
+    }
 
-                reply.data(b"hello\n");
-            }
-            _ => reply.error(ENOENT),
-        };
+    fn write(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: fuser::ReplyWrite,
+    ) {
+        match self.write_ino(ino, offset, data) {
+            Ok(written) => reply.written(written),
+            Err(e) => reply.error(e),
+        }
     }
 
     fn release(
@@ -165,6 +404,34 @@ where
         reply.ok();
     }
 
+    fn flush(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+        reply: fuser::ReplyEmpty,
+    ) {
+        match self.io.flush() {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.into()),
+        }
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        match self.io.sync() {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.into()),
+        }
+    }
+
     fn opendir(
         &mut self,
         _req: &fuser::Request<'_>,
@@ -188,37 +455,55 @@ where
             return;
         }
 
-        let (c, i) = from_inode(ino);
-        let parent_dir = DirectoryEntry::from(&self.read_raw_directory_entry(c, i));
-
-        let chain;
+        let dir = match self.read_ino_dir(ino) {
+            Ok(dir) => dir,
+            Err(e) => {
+                reply.error(e);
+                return;
+            }
+        };
 
-        match parent_dir {
-            DirectoryEntry::Invalid => reply.error(EBADFD),
-            DirectoryEntry::LongFileName(_) => reply.error(EBADFD),
-            DirectoryEntry::Directory(inode) => {
-                chain = self.get_chain(inode.start_cluster);
+        for (idx, (e, c, i)) in dir.iter().enumerate().skip(offset as usize) {
+            let (t, name) = match e {
+                DirectoryEntry::Directory(i) => (FileType::Directory, i.name.to_string()),
+                DirectoryEntry::File(i) => (FileType::RegularFile, i.name.to_string()),
+                _ => continue,
+            };
 
-                let dir = &self.read_dir(&chain)[offset as usize..];
+            let buf_full = reply.add(
+                self.inodes.inode(*c, *i),
+                (idx + 1) as i64,
+                t,
+                OsString::from(&name),
+            );
 
-                for (idx, (e, c, i)) in dir.iter().enumerate() {
-                    let (t, name) = match e {
-                        DirectoryEntry::Directory(i) => (FileType::Directory, i.name.to_string()),
-                        DirectoryEntry::File(i) => (FileType::RegularFile, i.name.to_string()),
-                        _ => continue,
-                    };
+            if buf_full {
+                break;
+            }
+        }
 
-                    let buf_full =
-                        reply.add(to_inode(*c, *i), (idx + 1) as i64, t, OsString::from(&name));
+        reply.ok();
+    }
 
-                    if buf_full {
-                        break;
-                    }
-                }
+    fn statfs(&mut self, _req: &fuser::Request<'_>, _ino: u64, reply: fuser::ReplyStatfs) {
+        let counts = self
+            .free_clusters()
+            .and_then(|free| Ok((free, self.used_entries()?)));
 
-                reply.ok();
-            }
-            DirectoryEntry::File(_) => reply.error(EBADFD),
+        // every new file or directory takes at least one cluster, so the free
+        // clusters are an upper bound for the files that can still be created
+        match counts {
+            Ok((free, used)) => reply.statfs(
+                self.io.fat_length() as u64,
+                free as u64,
+                free as u64,
+                used + free as u64,
+                free as u64,
+                self.io.cluster_size(),
+                NAME_MAX,
+                self.io.cluster_size(),
+            ),
+            Err(e) => reply.error(e.into()),
         }
     }
 
@@ -232,4 +517,25 @@ where
     ) {
         reply.ok()
     }
+
+    fn fsyncdir(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        match self.io.sync() {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.into()),
+        }
+    }
+
+    fn destroy(&mut self) {
+        // unmounting can't fail, so all that's left is to tell somebody
+        if let Err(e) = self.io.sync() {
+            eprintln!("naths_fat_fs: syncing on unmount failed: {}", e);
+        }
+    }
 }
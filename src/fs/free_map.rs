@@ -0,0 +1,82 @@
+use crate::FatEntry;
+
+/// In-memory copy of which clusters are free, so allocating does not have to scan the FAT.
+pub struct FreeMap {
+    words: Vec<u64>,
+    length: FatEntry,
+    free: u32,
+    hint: FatEntry,
+}
+
+impl FreeMap {
+    pub fn new(length: FatEntry) -> Self {
+        FreeMap {
+            words: vec![0; (length as usize).div_ceil(64)],
+            length,
+            free: 0,
+            hint: 1,
+        }
+    }
+
+    pub fn free(&self) -> u32 {
+        self.free
+    }
+
+    pub fn is_free(&self, cluster: FatEntry) -> bool {
+        let bit = (cluster - 1) as usize;
+
+        self.words[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    pub fn set_free(&mut self, cluster: FatEntry, free: bool) {
+        if self.is_free(cluster) == free {
+            return;
+        }
+
+        let bit = (cluster - 1) as usize;
+
+        self.words[bit / 64] ^= 1 << (bit % 64);
+
+        if free {
+            self.free += 1;
+        } else {
+            self.free -= 1;
+        }
+    }
+
+    /// Next free cluster at or after the hint, wrapping around at the end of the FAT.
+    pub fn next_free(&self) -> Option<FatEntry> {
+        if self.free == 0 {
+            return None;
+        }
+
+        let start = (self.hint - 1) as usize;
+        let words = self.words.len();
+
+        for n in 0..=words {
+            let idx = (start / 64 + n) % words;
+            let mut word = self.words[idx];
+
+            // the first word is visited twice, only look behind the hint the first time
+            if n == 0 {
+                word &= !0 << (start % 64);
+            } else if n == words {
+                word &= !(!0 << (start % 64));
+            }
+
+            if word != 0 {
+                return Some((idx * 64 + word.trailing_zeros() as usize + 1) as FatEntry);
+            }
+        }
+
+        None
+    }
+
+    pub fn set_hint(&mut self, cluster: FatEntry) {
+        self.hint = if cluster >= self.length {
+            1
+        } else {
+            cluster + 1
+        };
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use crate::{
+    utility::fs_utility::{from_inode, to_inode},
+    FatEntry,
+};
+
+const FIRST_ALIAS: u64 = 1 << 48;
+
+/// Keeps inode numbers handed out to the kernel valid after their directory entry moved.
+#[derive(Clone)]
+pub struct InodeMap {
+    by_inode: HashMap<u64, (FatEntry, u32)>,
+    by_location: HashMap<(FatEntry, u32), u64>,
+    next_alias: u64,
+    entries_per_cluster: u32,
+}
+
+impl InodeMap {
+    pub fn new(entries_per_cluster: u32) -> Self {
+        InodeMap {
+            by_inode: HashMap::new(),
+            by_location: HashMap::new(),
+            next_alias: FIRST_ALIAS,
+            entries_per_cluster,
+        }
+    }
+
+    pub fn resolve(&self, inode: u64) -> (FatEntry, u32) {
+        match self.by_inode.get(&inode) {
+            Some(location) => *location,
+            None => from_inode(inode, self.entries_per_cluster),
+        }
+    }
+
+    pub fn inode(&mut self, cluster: FatEntry, idx: u32) -> u64 {
+        if let Some(inode) = self.by_location.get(&(cluster, idx)) {
+            return *inode;
+        }
+
+        let natural = to_inode(cluster, idx, self.entries_per_cluster);
+
+        if !self.by_inode.contains_key(&natural) {
+            return natural;
+        }
+
+        let alias = self.next_alias;
+        self.next_alias += 1;
+
+        self.assign(alias, cluster, idx);
+
+        alias
+    }
+
+    pub fn take(&mut self, cluster: FatEntry, idx: u32) -> u64 {
+        let inode = self.inode(cluster, idx);
+
+        self.by_location.remove(&(cluster, idx));
+        self.by_inode.remove(&inode);
+
+        inode
+    }
+
+    pub fn assign(&mut self, inode: u64, cluster: FatEntry, idx: u32) {
+        if inode != to_inode(cluster, idx, self.entries_per_cluster) {
+            self.by_inode.insert(inode, (cluster, idx));
+            self.by_location.insert((cluster, idx), inode);
+        }
+    }
+
+    pub fn moved(&mut self, from: (FatEntry, u32), to: (FatEntry, u32)) {
+        let inode = self.take(from.0, from.1);
+
+        self.take(to.0, to.1);
+        self.assign(inode, to.0, to.1);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    consts::{DIR_ENTRY_SIZE, FRE, JOURNAL_MAGIC},
+    error::FsError,
+    utility::le_bytes_to_u32,
+    DirEntry, FatEntry,
+};
+
+use super::basic_fs_io::BaseIO;
+
+const FAT_RECORD: u8 = 1;
+const DIR_ENTRY_RECORD: u8 = 2;
+
+const FAT_RECORD_SIZE: usize = 9;
+
+/// Metadata writes that have to reach the disk together.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub fat: BTreeMap<FatEntry, FatEntry>,
+    pub entries: BTreeMap<(FatEntry, u32), DirEntry>,
+}
+
+impl Transaction {
+    pub fn is_empty(&self) -> bool {
+        self.fat.is_empty() && self.entries.is_empty()
+    }
+
+    /// Lays the FAT entries this transaction writes over `fat`, which starts at
+    /// cluster `start`.
+    pub fn overlay_fat(&self, start: FatEntry, fat: &mut [FatEntry]) {
+        for (cluster, entry) in self
+            .fat
+            .range(start..start.saturating_add(fat.len() as u32))
+        {
+            fat[(cluster - start) as usize] = *entry;
+        }
+    }
+
+    /// Lays the directory entries this transaction writes over the content of
+    /// `cluster`.
+    pub fn overlay_cluster(&self, cluster: FatEntry, content: &mut [u8]) {
+        for ((_, idx), entry) in self.entries.range((cluster, 0)..=(cluster, u32::MAX)) {
+            let offset = (idx * DIR_ENTRY_SIZE) as usize;
+            content[offset..offset + entry.len()].copy_from_slice(entry);
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![];
+
+        for (cluster, entry) in &self.fat {
+            payload.push(FAT_RECORD);
+            payload.extend_from_slice(&cluster.to_le_bytes());
+            payload.extend_from_slice(&entry.to_le_bytes());
+        }
+
+        for ((cluster, idx), entry) in &self.entries {
+            payload.push(DIR_ENTRY_RECORD);
+            payload.extend_from_slice(&cluster.to_le_bytes());
+            payload.extend_from_slice(&idx.to_le_bytes());
+            payload.extend_from_slice(entry);
+        }
+
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        let mut transaction = Transaction::default();
+        let mut pos = 0;
+
+        while pos < payload.len() {
+            let record = payload.get(pos + 1..pos + 9)?;
+            let cluster = le_bytes_to_u32(&record[0..4]);
+            let value = le_bytes_to_u32(&record[4..8]);
+
+            match payload[pos] {
+                FAT_RECORD => {
+                    transaction.fat.insert(cluster, value);
+                    pos += FAT_RECORD_SIZE;
+                }
+                DIR_ENTRY_RECORD => {
+                    let end = pos + 9 + DIR_ENTRY_SIZE as usize;
+
+                    let mut entry = [0u8; DIR_ENTRY_SIZE as usize];
+                    entry.copy_from_slice(payload.get(pos + 9..end)?);
+
+                    transaction.entries.insert((cluster, value), entry);
+                    pos = end;
+                }
+                _ => return None,
+            }
+        }
+
+        Some(transaction)
+    }
+}
+
+// bytes the transaction clusters behind the header can hold
+fn capacity<B: BaseIO>(io: &B, length: u32) -> usize {
+    (length as usize - 1) * io.cluster_size() as usize
+}
+
+// FNV-1a, enough to tell a torn write from a complete one
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811C_9DC5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn write_header<B: BaseIO>(io: &mut B, start: FatEntry, payload: &[u8]) -> Result<(), FsError> {
+    let length = (payload.len() as u32).to_le_bytes();
+
+    let mut summed = length.to_vec();
+    summed.extend_from_slice(payload);
+
+    let mut header = vec![0u8; io.cluster_size() as usize];
+
+    header[0..4].copy_from_slice(&JOURNAL_MAGIC);
+    header[4..8].copy_from_slice(&length);
+    header[8..12].copy_from_slice(&checksum(&summed).to_le_bytes());
+
+    io.write_cluster(start, &header)
+}
+
+/// Marks the journal as empty.
+pub fn clear<B: BaseIO>(io: &mut B, start: FatEntry) -> Result<(), FsError> {
+    write_header(io, start, &[])
+}
+
+/// Writes `transaction` to the journal starting at cluster `start` and syncs
+/// it. Once this returned the transaction survives a crash. Returns false
+/// without writing anything if the transaction doesn't fit.
+pub fn log<B: BaseIO>(
+    io: &mut B,
+    start: FatEntry,
+    length: u32,
+    transaction: &Transaction,
+) -> Result<bool, FsError> {
+    let cluster_size = io.cluster_size() as usize;
+    let payload = transaction.encode();
+
+    if payload.len() > capacity(io, length) {
+        return Ok(false);
+    }
+
+    for (n, chunk) in payload.chunks(cluster_size).enumerate() {
+        let mut cluster = chunk.to_vec();
+        cluster.resize(cluster_size, 0);
+
+        io.write_cluster(start + 1 + n as FatEntry, &cluster)?;
+    }
+
+    write_header(io, start, &payload)?;
+    io.sync()?;
+
+    Ok(true)
+}
+
+pub fn apply<B: BaseIO>(io: &mut B, transaction: &Transaction) -> Result<(), FsError> {
+    for (cluster, entry) in &transaction.fat {
+        io.write_fat_entry(*cluster, *entry)?;
+    }
+
+    for ((cluster, idx), entry) in &transaction.entries {
+        io.write_raw_directory_entry(*cluster, *idx, entry)?;
+    }
+
+    Ok(())
+}
+
+/// Logs `transaction`, applies it and clears the journal again.
+///
+/// If it's too big for the journal, the clusters it frees are split off and
+/// freed in batches after the rest was committed, so a crash in between only
+/// leaves orphaned clusters behind for fsck. Fails with
+/// `FsError::JournalFull` without writing anything if even the rest doesn't fit.
+pub fn commit<B: BaseIO>(
+    io: &mut B,
+    start: FatEntry,
+    length: u32,
+    transaction: &Transaction,
+) -> Result<(), FsError> {
+    if log(io, start, length, transaction)? {
+        return finish(io, start, transaction);
+    }
+
+    let freed: Vec<FatEntry> = transaction
+        .fat
+        .iter()
+        .filter(|(_, entry)| **entry == FRE)
+        .map(|(cluster, _)| *cluster)
+        .collect();
+
+    let mut rest = Transaction {
+        fat: transaction.fat.clone(),
+        entries: transaction.entries.clone(),
+    };
+    rest.fat.retain(|_, entry| *entry != FRE);
+
+    if !log(io, start, length, &rest)? {
+        return Err(FsError::JournalFull);
+    }
+
+    finish(io, start, &rest)?;
+
+    for chunk in freed.chunks(capacity(io, length) / FAT_RECORD_SIZE) {
+        let batch = Transaction {
+            fat: chunk.iter().map(|cluster| (*cluster, FRE)).collect(),
+            entries: BTreeMap::new(),
+        };
+
+        log(io, start, length, &batch)?;
+        finish(io, start, &batch)?;
+    }
+
+    Ok(())
+}
+
+// applies a logged transaction and clears the journal
+fn finish<B: BaseIO>(
+    io: &mut B,
+    start: FatEntry,
+    transaction: &Transaction,
+) -> Result<(), FsError> {
+    apply(io, transaction)?;
+    io.sync()?;
+
+    // a stale transaction replayed later would undo whatever was written after it
+    clear(io, start)?;
+    io.sync()
+}
+
+/// Reads the transaction waiting in the journal without writing anything.
+/// Returns `None` if the journal is empty or holds a torn transaction.
+pub fn pending<B: BaseIO>(
+    io: &mut B,
+    start: FatEntry,
+    length: u32,
+) -> Result<Option<Transaction>, FsError> {
+    let header = io.read_cluster(start)?;
+    let payload_length = le_bytes_to_u32(&header[4..8]) as usize;
+
+    if header[0..4] != JOURNAL_MAGIC || payload_length == 0 || payload_length > capacity(io, length)
+    {
+        return Ok(None);
+    }
+
+    let mut summed = header[4..8].to_vec();
+
+    for n in 0..payload_length.div_ceil(io.cluster_size() as usize) {
+        summed.extend_from_slice(&io.read_cluster(start + 1 + n as FatEntry)?);
+    }
+
+    summed.truncate(4 + payload_length);
+
+    if checksum(&summed) != le_bytes_to_u32(&header[8..12]) {
+        return Ok(None);
+    }
+
+    Ok(Transaction::decode(&summed[4..]))
+}
+
+/// Finishes a transaction that was logged before a crash, or throws away one
+/// that wasn't logged completely. Returns whether a transaction was replayed.
+pub fn replay<B: BaseIO>(io: &mut B, start: FatEntry, length: u32) -> Result<bool, FsError> {
+    let header = io.read_cluster(start)?;
+
+    if header[0..4] == JOURNAL_MAGIC && le_bytes_to_u32(&header[4..8]) == 0 {
+        return Ok(false);
+    }
+
+    let transaction = pending(io, start, length)?;
+
+    if let Some(transaction) = &transaction {
+        apply(io, transaction)?;
+        io.sync()?;
+    }
+
+    clear(io, start)?;
+    io.sync()?;
+
+    Ok(transaction.is_some())
+}
@@ -0,0 +1,123 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{error::FsError, Cluster, DirEntry, FatEntry};
+
+use super::basic_fs_io::BaseIO;
+
+/// `BaseIO` handle that can be cloned, e.g. to keep access to the device while
+/// the `FileSystem` using it is owned by a mount session.
+pub struct SharedIO<B>
+where
+    B: BaseIO,
+{
+    inner: Arc<Mutex<B>>,
+    fat_length: FatEntry,
+    cluster_size: u32,
+}
+
+impl<B> SharedIO<B>
+where
+    B: BaseIO,
+{
+    pub fn new(inner: B) -> Self {
+        SharedIO {
+            fat_length: inner.fat_length(),
+            cluster_size: inner.cluster_size(),
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, B> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Gives back the wrapped `BaseIO` if this is the last handle to it.
+    pub fn try_into_inner(self) -> Result<B, Self> {
+        let fat_length = self.fat_length;
+        let cluster_size = self.cluster_size;
+
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => Ok(inner.into_inner().unwrap_or_else(|e| e.into_inner())),
+            Err(inner) => Err(SharedIO {
+                inner,
+                fat_length,
+                cluster_size,
+            }),
+        }
+    }
+}
+
+impl<B> Clone for SharedIO<B>
+where
+    B: BaseIO,
+{
+    fn clone(&self) -> Self {
+        SharedIO {
+            inner: Arc::clone(&self.inner),
+            fat_length: self.fat_length,
+            cluster_size: self.cluster_size,
+        }
+    }
+}
+
+impl<B> BaseIO for SharedIO<B>
+where
+    B: BaseIO,
+{
+    fn fat_length(&self) -> FatEntry {
+        self.fat_length
+    }
+
+    fn cluster_size(&self) -> u32 {
+        self.cluster_size
+    }
+
+    fn read_fat_entry(&mut self, cluster: FatEntry) -> Result<FatEntry, FsError> {
+        self.lock().read_fat_entry(cluster)
+    }
+
+    fn write_fat_entry(&mut self, cluster: FatEntry, entry: FatEntry) -> Result<(), FsError> {
+        self.lock().write_fat_entry(cluster, entry)
+    }
+
+    fn read_cluster(&mut self, cluster: FatEntry) -> Result<Cluster, FsError> {
+        self.lock().read_cluster(cluster)
+    }
+
+    fn write_cluster(&mut self, cluster: FatEntry, cluster_content: &[u8]) -> Result<(), FsError> {
+        self.lock().write_cluster(cluster, cluster_content)
+    }
+
+    fn read_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+    ) -> Result<DirEntry, FsError> {
+        self.lock().read_raw_directory_entry(cluster, idx)
+    }
+
+    fn write_raw_directory_entry(
+        &mut self,
+        cluster: FatEntry,
+        idx: u32,
+        entry: &DirEntry,
+    ) -> Result<(), FsError> {
+        self.lock().write_raw_directory_entry(cluster, idx, entry)
+    }
+
+    fn read_fat(&mut self, start: FatEntry, count: u32) -> Result<Vec<FatEntry>, FsError> {
+        self.lock().read_fat(start, count)
+    }
+
+    fn flush(&mut self) -> Result<(), FsError> {
+        self.lock().flush()
+    }
+
+    fn sync(&mut self) -> Result<(), FsError> {
+        self.lock().sync()
+    }
+
+    fn journal(&self) -> Option<(FatEntry, u32)> {
+        self.lock().journal()
+    }
+}
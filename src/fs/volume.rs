@@ -0,0 +1,366 @@
+use std::{
+    cmp::min,
+    io::{self, Read, Seek, SeekFrom, Write},
+    time::SystemTime,
+};
+
+use libc::{c_int, EINVAL, EISDIR, ENOENT};
+
+use crate::FatEntry;
+
+use super::{
+    basic_fs_io::BaseIO,
+    directory::{DirectoryEntry, Inode},
+    walk::{Walk, WalkOrder},
+    FileSystem,
+};
+
+pub const ROOT: (FatEntry, u32) = (1, 0);
+
+const FILE_PERMISSION: u16 = 0o644;
+const DIR_PERMISSION: u16 = 0o755;
+
+fn errno(e: c_int) -> io::Error {
+    io::Error::from_raw_os_error(e)
+}
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub name: String,
+    pub ino: u64,
+    pub is_dir: bool,
+    pub len: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub permission: u16,
+    pub atime: SystemTime,
+    pub mtime: SystemTime,
+    pub ctime: SystemTime,
+}
+
+impl Metadata {
+    fn new(ino: u64, entry: &DirectoryEntry) -> io::Result<Self> {
+        let (is_dir, inode) = match entry {
+            DirectoryEntry::Directory(inode) => (true, inode),
+            DirectoryEntry::File(inode) => (false, inode),
+            _ => return Err(errno(ENOENT)),
+        };
+
+        Ok(Metadata {
+            name: inode.name.clone(),
+            ino,
+            is_dir,
+            len: inode.length,
+            uid: inode.uid,
+            gid: inode.gid,
+            permission: inode.permission,
+            atime: inode.atime,
+            mtime: inode.mtime,
+            ctime: inode.ctime,
+        })
+    }
+}
+
+/// Path based access to a `FileSystem`, for building and inspecting images
+/// without mounting them. Paths are `/` separated and always start at the root.
+pub struct Volume<B>
+where
+    B: BaseIO,
+{
+    pub fs: FileSystem<B>,
+}
+
+impl<B> Volume<B>
+where
+    B: BaseIO,
+{
+    pub fn new(io: B) -> Self {
+        Volume {
+            fs: FileSystem::new(io),
+        }
+    }
+
+    pub fn into_inner(self) -> FileSystem<B> {
+        self.fs
+    }
+
+    /// Resolves `path` to the location of its directory entry.
+    pub fn lookup(&mut self, path: &str) -> io::Result<(FatEntry, u32)> {
+        let mut stack = vec![ROOT];
+
+        for name in path.split('/') {
+            match name {
+                "" | "." => (),
+                ".." => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                }
+                name => {
+                    let (c, i) = stack[stack.len() - 1];
+                    let chain = self.fs.dir_chain(c, i).map_err(errno)?;
+
+                    let (_, c, i) = self
+                        .fs
+                        .find_in_dir(&chain, name)?
+                        .ok_or_else(|| errno(ENOENT))?;
+
+                    stack.push((c, i));
+                }
+            }
+        }
+
+        Ok(stack[stack.len() - 1])
+    }
+
+    fn parent<'p>(&mut self, path: &'p str) -> io::Result<((FatEntry, u32), &'p str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(errno(EINVAL));
+        }
+
+        Ok((self.lookup(parent)?, name))
+    }
+
+    pub fn open(&mut self, path: &str) -> io::Result<File<'_, B>> {
+        let (cluster, idx) = self.lookup(path)?;
+
+        File::new(&mut self.fs, cluster, idx)
+    }
+
+    /// Opens `path` for writing, creating it if needed and truncating it otherwise.
+    pub fn create(&mut self, path: &str) -> io::Result<File<'_, B>> {
+        let ((c, i), name) = self.parent(path)?;
+
+        let (cluster, idx) = match self.fs.create_file(c, i, name, 0, 0, FILE_PERMISSION) {
+            Ok(location) => location,
+            Err(libc::EEXIST) => self.lookup(path)?,
+            Err(e) => return Err(errno(e)),
+        };
+
+        let mut file = File::new(&mut self.fs, cluster, idx)?;
+        file.set_len(0)?;
+
+        Ok(file)
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> io::Result<()> {
+        let ((c, i), name) = self.parent(path)?;
+
+        self.fs
+            .create_dir(c, i, name, 0, 0, DIR_PERMISSION)
+            .map_err(errno)?;
+
+        Ok(())
+    }
+
+    pub fn remove_file(&mut self, path: &str) -> io::Result<()> {
+        let ((c, i), name) = self.parent(path)?;
+
+        self.fs.remove_file(c, i, name).map_err(errno)
+    }
+
+    pub fn remove_dir(&mut self, path: &str) -> io::Result<()> {
+        let ((c, i), name) = self.parent(path)?;
+
+        self.fs.remove_dir(c, i, name).map_err(errno)
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        let ((c, i), name) = self.parent(from)?;
+        let ((nc, ni), new_name) = self.parent(to)?;
+
+        self.fs
+            .rename(c, i, name, nc, ni, new_name, 0)
+            .map_err(errno)
+    }
+
+    pub fn set_permissions(&mut self, path: &str, permission: u16) -> io::Result<()> {
+        self.update(path, |inode| inode.permission = permission & 0o7777)
+    }
+
+    pub fn set_owner(&mut self, path: &str, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        self.update(path, |inode| {
+            inode.uid = uid.unwrap_or(inode.uid);
+            inode.gid = gid.unwrap_or(inode.gid);
+        })
+    }
+
+    fn update(&mut self, path: &str, change: impl FnOnce(&mut Inode)) -> io::Result<()> {
+        let (c, i) = self.lookup(path)?;
+        let mut entry = self.fs.read_directory_entry(c, i)?;
+
+        match &mut entry {
+            DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) => {
+                change(inode);
+                inode.ctime = SystemTime::now();
+            }
+            _ => return Err(errno(ENOENT)),
+        }
+
+        Ok(self.fs.write_directory_entry(c, i, &entry)?)
+    }
+
+    pub fn metadata(&mut self, path: &str) -> io::Result<Metadata> {
+        let (c, i) = self.lookup(path)?;
+        let entry = self.fs.read_directory_entry(c, i)?;
+
+        Metadata::new(self.fs.inodes.inode(c, i), &entry)
+    }
+
+    pub fn walk(&mut self, path: &str, order: WalkOrder) -> io::Result<Walk<'_, B>> {
+        let (c, i) = self.lookup(path)?;
+
+        self.fs.walk_from(c, i, path, order).map_err(errno)
+    }
+
+    /// Lists a directory without its `.` and `..` entries.
+    pub fn read_dir(&mut self, path: &str) -> io::Result<Vec<Metadata>> {
+        let (c, i) = self.lookup(path)?;
+        let chain = self.fs.dir_chain(c, i).map_err(errno)?;
+
+        let mut entries = vec![];
+
+        for (entry, c, i) in self.fs.read_dir(&chain)? {
+            match &entry {
+                DirectoryEntry::Directory(inode) if inode.name == "." || inode.name == ".." => {}
+                DirectoryEntry::Directory(_) | DirectoryEntry::File(_) => {
+                    entries.push(Metadata::new(self.fs.inodes.inode(c, i), &entry)?)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+impl<B> From<FileSystem<B>> for Volume<B>
+where
+    B: BaseIO,
+{
+    fn from(fs: FileSystem<B>) -> Self {
+        Volume { fs }
+    }
+}
+
+/// An open regular file. Every write updates its directory entry right away.
+pub struct File<'a, B>
+where
+    B: BaseIO,
+{
+    fs: &'a mut FileSystem<B>,
+    cluster: FatEntry,
+    idx: u32,
+    inode: Inode,
+    pos: u64,
+}
+
+impl<'a, B> File<'a, B>
+where
+    B: BaseIO,
+{
+    fn new(fs: &'a mut FileSystem<B>, cluster: FatEntry, idx: u32) -> io::Result<Self> {
+        let inode = match fs.read_directory_entry(cluster, idx)? {
+            DirectoryEntry::File(inode) => inode,
+            DirectoryEntry::Directory(_) => return Err(errno(EISDIR)),
+            _ => return Err(errno(ENOENT)),
+        };
+
+        Ok(File {
+            fs,
+            cluster,
+            idx,
+            inode,
+            pos: 0,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.inode.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inode.length == 0
+    }
+
+    pub fn metadata(&mut self) -> io::Result<Metadata> {
+        let ino = self.fs.inodes.inode(self.cluster, self.idx);
+
+        Metadata::new(ino, &DirectoryEntry::File(self.inode.clone()))
+    }
+
+    pub fn set_len(&mut self, size: u64) -> io::Result<()> {
+        let (cluster, idx) = (self.cluster, self.idx);
+        let mut inode = self.inode.clone();
+
+        self.fs.transaction(|fs| {
+            fs.truncate(&mut inode, size)?;
+            fs.write_directory_entry(cluster, idx, &DirectoryEntry::File(inode.clone()))
+        })?;
+
+        self.inode = inode;
+
+        Ok(())
+    }
+
+    fn write_entry(&mut self) -> io::Result<()> {
+        let entry = DirectoryEntry::File(self.inode.clone());
+
+        Ok(self
+            .fs
+            .write_directory_entry(self.cluster, self.idx, &entry)?)
+    }
+}
+
+impl<B> Read for File<'_, B>
+where
+    B: BaseIO,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = min(buf.len(), u32::MAX as usize) as u32;
+        let data = self.fs.read_data(&self.inode, self.pos, size)?;
+
+        buf[..data.len()].copy_from_slice(&data);
+        self.pos += data.len() as u64;
+
+        Ok(data.len())
+    }
+}
+
+impl<B> Write for File<'_, B>
+where
+    B: BaseIO,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.fs.write_data(&mut self.inode, self.pos, buf)?;
+
+        self.write_entry()?;
+        self.pos += written as u64;
+
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.fs.flush()?)
+    }
+}
+
+impl<B> Seek for File<'_, B>
+where
+    B: BaseIO,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.inode.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        self.pos = pos.ok_or_else(|| errno(EINVAL))?;
+
+        Ok(self.pos)
+    }
+}
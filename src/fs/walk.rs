@@ -0,0 +1,158 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::Display,
+    vec::IntoIter,
+};
+
+use crate::{error::FsError, FatEntry};
+
+use super::{basic_fs_io::BaseIO, directory::DirectoryEntry, FileSystem};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkOrder {
+    DepthFirst,
+    BreadthFirst,
+}
+
+/// A directory that could not be read or descended into. The walk carries on
+/// with the rest of the tree.
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: String,
+    pub error: FsError,
+}
+
+impl Display for WalkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for WalkError {}
+
+impl From<WalkError> for FsError {
+    fn from(value: WalkError) -> Self {
+        value.error
+    }
+}
+
+struct Frame {
+    path: String,
+    entries: IntoIter<(DirectoryEntry, FatEntry, u32)>,
+}
+
+/// Iterator over everything below a directory, yielding the path, the entry
+/// and the inode number of each file and directory. `.` and `..` are skipped.
+pub struct Walk<'a, B>
+where
+    B: BaseIO,
+{
+    fs: &'a mut FileSystem<B>,
+    order: WalkOrder,
+    frames: VecDeque<Frame>,
+    descend: Option<(String, FatEntry)>,
+    visited: HashSet<FatEntry>,
+}
+
+impl<'a, B> Walk<'a, B>
+where
+    B: BaseIO,
+{
+    pub(super) fn new(
+        fs: &'a mut FileSystem<B>,
+        path: &str,
+        start_cluster: FatEntry,
+        order: WalkOrder,
+    ) -> Self {
+        Walk {
+            fs,
+            order,
+            frames: VecDeque::new(),
+            descend: Some((path.to_string(), start_cluster)),
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Skips the contents of the directory returned last.
+    pub fn prune(&mut self) {
+        self.descend = None;
+    }
+
+    fn enter(&mut self, path: String, start_cluster: FatEntry) -> Result<(), WalkError> {
+        if !self.visited.insert(start_cluster) {
+            return Err(WalkError {
+                path,
+                error: FsError::DirectoryLoop(start_cluster),
+            });
+        }
+
+        let dir = self
+            .fs
+            .get_chain(start_cluster)
+            .and_then(|chain| self.fs.read_dir(&chain));
+
+        match dir {
+            Ok(dir) => {
+                self.frames.push_back(Frame {
+                    path,
+                    entries: dir.into_iter(),
+                });
+
+                Ok(())
+            }
+            Err(error) => Err(WalkError { path, error }),
+        }
+    }
+}
+
+impl<B> Iterator for Walk<'_, B>
+where
+    B: BaseIO,
+{
+    type Item = Result<(String, DirectoryEntry, u64), WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((path, start_cluster)) = self.descend.take() {
+            if let Err(e) = self.enter(path, start_cluster) {
+                return Some(Err(e));
+            }
+        }
+
+        loop {
+            let frame = match self.order {
+                WalkOrder::DepthFirst => self.frames.back_mut()?,
+                WalkOrder::BreadthFirst => self.frames.front_mut()?,
+            };
+
+            let (entry, c, i) = match frame.entries.next() {
+                Some(next) => next,
+                None => {
+                    match self.order {
+                        WalkOrder::DepthFirst => self.frames.pop_back(),
+                        WalkOrder::BreadthFirst => self.frames.pop_front(),
+                    };
+
+                    continue;
+                }
+            };
+
+            let (inode, directory) = match &entry {
+                DirectoryEntry::Directory(inode) => (inode, true),
+                DirectoryEntry::File(inode) => (inode, false),
+                _ => continue,
+            };
+
+            if inode.name == "." || inode.name == ".." {
+                continue;
+            }
+
+            let path = format!("{}/{}", frame.path.trim_end_matches('/'), inode.name);
+
+            if directory {
+                self.descend = Some((path.clone(), inode.start_cluster));
+            }
+
+            return Some(Ok((path, entry, self.fs.inodes.inode(c, i))));
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    time::SystemTime,
+};
+
+use crate::{
+    consts::{DNA, EOC, FRE},
+    error::FsError,
+    fs::{
+        basic_fs_io::BaseIO,
+        directory::{DirectoryEntry, Inode},
+        FileSystem,
+    },
+    Chain, FatEntry,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Problem {
+    BadStart {
+        path: String,
+        cluster: FatEntry,
+    },
+    BadLink {
+        path: String,
+        cluster: FatEntry,
+        next: FatEntry,
+    },
+    Loop {
+        path: String,
+        cluster: FatEntry,
+    },
+    CrossLinked {
+        path: String,
+        cluster: FatEntry,
+        owner: String,
+    },
+    LengthMismatch {
+        path: String,
+        length: u64,
+        clusters: usize,
+    },
+    BrokenDotEntry {
+        path: String,
+        name: &'static str,
+    },
+    Orphaned {
+        cluster: FatEntry,
+        clusters: usize,
+    },
+    Unreadable {
+        path: String,
+        error: String,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::BadStart { path, cluster } => {
+                write!(f, "{}: invalid start cluster {:#X}", path, cluster)
+            }
+            Problem::BadLink {
+                path,
+                cluster,
+                next,
+            } => write!(
+                f,
+                "{}: cluster {} links to invalid cluster {:#X}",
+                path, cluster, next
+            ),
+            Problem::Loop { path, cluster } => {
+                write!(f, "{}: chain loops back at cluster {}", path, cluster)
+            }
+            Problem::CrossLinked {
+                path,
+                cluster,
+                owner,
+            } => write!(f, "{}: cluster {} is also used by {}", path, cluster, owner),
+            Problem::LengthMismatch {
+                path,
+                length,
+                clusters,
+            } => write!(
+                f,
+                "{}: length {} does not match {} allocated clusters",
+                path, length, clusters
+            ),
+            Problem::BrokenDotEntry { path, name } => {
+                write!(f, "{}: broken \"{}\" entry", path, name)
+            }
+            Problem::Orphaned { cluster, clusters } => write!(
+                f,
+                "cluster {}: orphaned chain of {} clusters",
+                cluster, clusters
+            ),
+            Problem::Unreadable { path, error } => {
+                write!(f, "{}: unreadable directory: {}", path, error)
+            }
+        }
+    }
+}
+
+enum ChainEnd {
+    Eoc,
+    BadLink(FatEntry, FatEntry),
+    Loop(FatEntry),
+}
+
+struct Checker {
+    repair: bool,
+    owners: HashMap<FatEntry, String>,
+    problems: Vec<Problem>,
+}
+
+pub fn check<B>(fs: &mut FileSystem<B>, repair: bool) -> Result<Vec<Problem>, FsError>
+where
+    B: BaseIO,
+{
+    let mut checker = Checker {
+        repair,
+        owners: HashMap::new(),
+        problems: vec![],
+    };
+
+    checker.check_tree(fs)?;
+    checker.check_orphans(fs)?;
+
+    Ok(checker.problems)
+}
+
+fn is_valid(fs: &FileSystem<impl BaseIO>, cluster: FatEntry) -> bool {
+    cluster != FRE && cluster != DNA && cluster != EOC && cluster <= fs.io.fat_length()
+}
+
+fn child_path(parent: &str, name: &str) -> String {
+    format!("{}/{}", parent.trim_end_matches('/'), name)
+}
+
+impl Checker {
+    fn check_tree<B>(&mut self, fs: &mut FileSystem<B>) -> Result<(), FsError>
+    where
+        B: BaseIO,
+    {
+        let root = self.check_chain(fs, "/", 1)?;
+        let mut pending = vec![(String::from("/"), root, 1)];
+
+        while let Some((path, chain, parent)) = pending.pop() {
+            self.check_dot_entries(fs, &path, &chain, parent)?;
+
+            let dir = match fs.read_dir(&chain) {
+                Ok(dir) => dir,
+                Err(FsError::Io(e)) => return Err(FsError::Io(e)),
+                Err(e) => {
+                    self.problems.push(Problem::Unreadable {
+                        path,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            for (mut entry, c, i) in dir {
+                if c == chain[0] && i < 2 {
+                    continue;
+                }
+
+                let (inode, directory) = match &mut entry {
+                    DirectoryEntry::Directory(inode) => (inode, true),
+                    DirectoryEntry::File(inode) => (inode, false),
+                    _ => continue,
+                };
+
+                let entry_path = child_path(&path, &inode.name);
+                let start = inode.start_cluster;
+
+                let problem = if !is_valid(fs, start) {
+                    Some(Problem::BadStart {
+                        path: entry_path.clone(),
+                        cluster: start,
+                    })
+                } else {
+                    self.owners.get(&start).map(|owner| Problem::CrossLinked {
+                        path: entry_path.clone(),
+                        cluster: start,
+                        owner: owner.clone(),
+                    })
+                };
+
+                if let Some(problem) = problem {
+                    self.problems.push(problem);
+
+                    if self.repair {
+                        fs.invalidate_entry(&chain, c, i)?;
+                    }
+
+                    continue;
+                }
+
+                let child = self.check_chain(fs, &entry_path, start)?;
+
+                if directory {
+                    pending.push((entry_path, child, chain[0]));
+                } else {
+                    self.check_length(fs, &entry_path, inode, &child)?;
+
+                    if self.repair {
+                        fs.write_directory_entry(c, i, &entry)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scan_chain<B>(
+        &mut self,
+        fs: &mut FileSystem<B>,
+        start: FatEntry,
+    ) -> Result<(Chain, ChainEnd), FsError>
+    where
+        B: BaseIO,
+    {
+        let mut chain = vec![start];
+        let mut seen = HashSet::from([start]);
+
+        loop {
+            let cluster = chain[chain.len() - 1];
+            let next = fs.read_fat_entry(cluster)?;
+
+            if next == EOC {
+                return Ok((chain, ChainEnd::Eoc));
+            } else if !is_valid(fs, next) {
+                return Ok((chain, ChainEnd::BadLink(cluster, next)));
+            } else if !seen.insert(next) {
+                return Ok((chain, ChainEnd::Loop(cluster)));
+            }
+
+            chain.push(next);
+        }
+    }
+
+    fn check_chain<B>(
+        &mut self,
+        fs: &mut FileSystem<B>,
+        path: &str,
+        start: FatEntry,
+    ) -> Result<Chain, FsError>
+    where
+        B: BaseIO,
+    {
+        let (mut chain, end) = self.scan_chain(fs, start)?;
+
+        // the start cluster is checked by the caller, so a cross-link is always behind it
+        let shared = chain.iter().position(|c| self.owners.contains_key(c));
+
+        if let Some(pos) = shared {
+            self.problems.push(Problem::CrossLinked {
+                path: path.to_string(),
+                cluster: chain[pos],
+                owner: self.owners[&chain[pos]].clone(),
+            });
+
+            chain.truncate(pos);
+        } else {
+            match end {
+                ChainEnd::Eoc => (),
+                ChainEnd::BadLink(cluster, next) => self.problems.push(Problem::BadLink {
+                    path: path.to_string(),
+                    cluster,
+                    next,
+                }),
+                ChainEnd::Loop(cluster) => self.problems.push(Problem::Loop {
+                    path: path.to_string(),
+                    cluster,
+                }),
+            }
+        }
+
+        if self.repair && (shared.is_some() || !matches!(end, ChainEnd::Eoc)) {
+            fs.write_fat_entry(chain[chain.len() - 1], EOC)?;
+        }
+
+        for cluster in &chain {
+            self.owners.insert(*cluster, path.to_string());
+        }
+
+        Ok(chain)
+    }
+
+    fn check_length<B>(
+        &mut self,
+        fs: &mut FileSystem<B>,
+        path: &str,
+        inode: &mut Inode,
+        chain: &Chain,
+    ) -> Result<(), FsError>
+    where
+        B: BaseIO,
+    {
+        let needed = inode.length.div_ceil(fs.io.cluster_size() as u64).max(1) as usize;
+
+        if needed == chain.len() {
+            return Ok(());
+        }
+
+        self.problems.push(Problem::LengthMismatch {
+            path: path.to_string(),
+            length: inode.length,
+            clusters: chain.len(),
+        });
+
+        if !self.repair {
+            return Ok(());
+        }
+
+        if chain.len() > needed {
+            fs.write_fat_entry(chain[needed - 1], EOC)?;
+
+            for cluster in &chain[needed..] {
+                fs.write_fat_entry(*cluster, FRE)?;
+                self.owners.remove(cluster);
+            }
+        } else {
+            inode.length = chain.len() as u64 * fs.io.cluster_size() as u64;
+        }
+
+        Ok(())
+    }
+
+    fn check_dot_entries<B>(
+        &mut self,
+        fs: &mut FileSystem<B>,
+        path: &str,
+        chain: &Chain,
+        parent: FatEntry,
+    ) -> Result<(), FsError>
+    where
+        B: BaseIO,
+    {
+        let mut owner = None;
+        let mut broken = false;
+
+        for (idx, (name, start)) in [(".", chain[0]), ("..", parent)].into_iter().enumerate() {
+            match fs.read_directory_entry(chain[0], idx as u32) {
+                Ok(DirectoryEntry::Directory(inode))
+                    if inode.name == name && inode.start_cluster == start =>
+                {
+                    owner.get_or_insert((inode.uid, inode.gid, inode.permission));
+                }
+                Err(FsError::Io(e)) => return Err(FsError::Io(e)),
+                _ => {
+                    self.problems.push(Problem::BrokenDotEntry {
+                        path: path.to_string(),
+                        name,
+                    });
+                    broken = true;
+                }
+            }
+        }
+
+        if self.repair && broken {
+            let (uid, gid, permission) = owner.unwrap_or((0, 0, 0o755));
+
+            fs.write_dot_entries(chain[0], parent, uid, gid, permission)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_orphans<B>(&mut self, fs: &mut FileSystem<B>) -> Result<(), FsError>
+    where
+        B: BaseIO,
+    {
+        let mut orphans = HashMap::new();
+
+        for cluster in 1..=fs.io.fat_length() {
+            let next = fs.read_fat_entry(cluster)?;
+
+            if next != FRE && next != DNA && !self.owners.contains_key(&cluster) {
+                orphans.insert(cluster, next);
+            }
+        }
+
+        let targets: HashSet<FatEntry> = orphans.values().copied().collect();
+
+        let mut heads: Vec<FatEntry> = orphans
+            .keys()
+            .copied()
+            .filter(|c| !targets.contains(c))
+            .collect();
+        heads.sort();
+
+        let mut visited = HashSet::new();
+        let mut chains = vec![];
+
+        let mut remaining: Vec<FatEntry> = orphans.keys().copied().collect();
+        remaining.sort();
+
+        // heads first, then whatever is left over, which can only be loops
+        for head in heads.into_iter().chain(remaining) {
+            if visited.contains(&head) {
+                continue;
+            }
+
+            let mut chain = vec![head];
+            visited.insert(head);
+
+            while let Some(next) = orphans.get(&chain[chain.len() - 1]) {
+                if !orphans.contains_key(next) || !visited.insert(*next) {
+                    break;
+                }
+
+                chain.push(*next);
+            }
+
+            self.problems.push(Problem::Orphaned {
+                cluster: head,
+                clusters: chain.len(),
+            });
+
+            chains.push(chain);
+        }
+
+        if !self.repair || chains.is_empty() {
+            return Ok(());
+        }
+
+        let mut lost_found = match lost_and_found(fs)? {
+            Some(chain) => chain,
+            None => return Ok(()),
+        };
+
+        let now = SystemTime::now();
+
+        for chain in chains {
+            let last = chain[chain.len() - 1];
+
+            if orphans[&last] != EOC {
+                fs.write_fat_entry(last, EOC)?;
+            }
+
+            fs.insert_entry(
+                &mut lost_found,
+                &DirectoryEntry::File(Inode::new(
+                    format!("#{}", chain[0]),
+                    chain.len() as u64 * fs.io.cluster_size() as u64,
+                    0,
+                    0,
+                    0o600,
+                    now,
+                    now,
+                    now,
+                    1,
+                    chain[0],
+                )),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn lost_and_found<B>(fs: &mut FileSystem<B>) -> Result<Option<Chain>, FsError>
+where
+    B: BaseIO,
+{
+    let root = fs.get_chain(1)?;
+
+    let (c, i) = match fs.find_in_dir(&root, "lost+found")? {
+        Some((DirectoryEntry::Directory(_), c, i)) => (c, i),
+        Some(_) => return Ok(None),
+        None => match fs.create_dir(1, 0, "lost+found", 0, 0, 0o700) {
+            Ok(location) => location,
+            Err(_) => return Ok(None),
+        },
+    };
+
+    Ok(fs.dir_chain(c, i).ok())
+}
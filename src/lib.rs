@@ -1,13 +1,16 @@
-use consts::{CLUSTER_SIZE, DIR_ENTRY_SIZE};
+use consts::DIR_ENTRY_SIZE;
 use fs::directory::DirectoryEntry;
 
 pub mod consts;
+pub mod error;
+pub mod export;
 pub mod fs;
+pub mod fsck;
 pub mod mkfs;
 pub mod utility;
 
 pub type FatEntry = u32;
 pub type DirEntry = [u8; DIR_ENTRY_SIZE as usize];
-pub type Cluster = [u8; CLUSTER_SIZE as usize];
+pub type Cluster = Vec<u8>;
 pub type Chain = Vec<FatEntry>;
 pub type Dir = Vec<(DirectoryEntry, FatEntry, u32)>;
@@ -1,91 +1,325 @@
 use std::{
-    io::{Read, Seek, SeekFrom, Write},
-    time::SystemTime,
+    cmp::min,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
     consts::{
-        CLUSTER_SIZE, DATA_REGION, DIR_ENTRY_SIZE, EOC, FAT_ENTRY_SIZE, FAT_PADDING, FS_ID,
-        FS_VERSION,
+        DATA_REGION, DNA, EOC, FAT_ENTRY_SIZE, FAT_PADDING, FRE, FS_ID, FS_VERSION,
+        MIN_JOURNAL_CLUSTERS,
     },
+    error::FsError,
     fs::{
-        basic_fs_io::BaseIO,
+        basic_fs_io::{BaseIO, Device, FileSystemBasicIO},
         directory::{DirectoryEntry, Inode},
+        journal,
+        volume::ROOT,
         FileSystem,
     },
     utility::fs_utility::{
-        get_data_region_size, get_data_section_address, get_prelude_padding_size,
+        check_cluster_size, entries_per_cluster, get_data_region_size, get_data_section_address,
+        get_image_size, get_prelude_padding_size,
     },
-    DirEntry,
+    FatEntry,
 };
 
-pub fn write_prelude<W: Write + Seek>(fat_size: u32, dest: &mut W) {
-    dest.rewind().unwrap();
+const WRITE_BUFFER_SIZE: u64 = 1 << 20;
 
-    dest.write_all(&FS_ID).unwrap();
-    dest.write_all(&FS_VERSION).unwrap();
-    dest.write_all(&fat_size.to_le_bytes()).unwrap();
+pub fn write_prelude<W: Write + Seek>(
+    fat_size: u32,
+    cluster_size: u32,
+    dest: &mut W,
+) -> Result<(), FsError> {
+    check_cluster_size(cluster_size)?;
 
-    dest.write_all(&[FAT_PADDING; 18]).unwrap();
+    dest.rewind()?;
 
-    let fat_bytes = FAT_ENTRY_SIZE * fat_size;
+    dest.write_all(&FS_ID)?;
+    dest.write_all(&FS_VERSION)?;
+    dest.write_all(&fat_size.to_le_bytes())?;
+    dest.write_all(&cluster_size.to_le_bytes())?;
 
-    for _ in 0..fat_bytes {
-        dest.write_all(&[0u8]).unwrap();
+    // no journal until create_journal reserves one
+    dest.write_all(&[0u8; 8])?;
+    dest.write_all(&[FAT_PADDING; 6])?;
+
+    write_repeated(0u8, FAT_ENTRY_SIZE as u64 * fat_size as u64, dest)?;
+    write_repeated(FAT_PADDING, get_prelude_padding_size(fat_size), dest)
+}
+
+pub fn write_data_section<W: Write + Seek>(
+    fat_size: u32,
+    cluster_size: u32,
+    dest: &mut W,
+) -> Result<(), FsError> {
+    dest.seek(SeekFrom::Start(get_data_section_address(fat_size)))?;
+
+    write_repeated(
+        DATA_REGION,
+        get_data_region_size(fat_size, cluster_size),
+        dest,
+    )
+}
+
+fn write_repeated<W: Write>(byte: u8, count: u64, dest: &mut W) -> Result<(), FsError> {
+    let buffer = vec![byte; min(count, WRITE_BUFFER_SIZE) as usize];
+    let mut left = count;
+
+    while left > 0 {
+        let len = min(left, buffer.len() as u64) as usize;
+
+        dest.write_all(&buffer[..len])?;
+        left -= len as u64;
     }
 
-    for _ in 0..get_prelude_padding_size(fat_size) {
-        dest.write_all(&[FAT_PADDING]).unwrap();
+    Ok(())
+}
+
+pub fn format<T: Device>(fat_size: u32, cluster_size: u32, dest: &mut T) -> Result<(), FsError> {
+    write_prelude(fat_size, cluster_size, dest)?;
+    write_data_section(fat_size, cluster_size, dest)?;
+
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(dest)?);
+
+    write_root_dir(&mut fs)
+}
+
+/// Like `format`, but leaves the data region as it is. Only the root directory
+/// cluster is written, the image is extended to its full size if it is shorter.
+pub fn quick_format<T: Device>(
+    fat_size: u32,
+    cluster_size: u32,
+    dest: &mut T,
+) -> Result<(), FsError> {
+    write_prelude(fat_size, cluster_size, dest)?;
+
+    let image_size = get_image_size(fat_size, cluster_size);
+
+    if dest.seek(SeekFrom::End(0))? < image_size {
+        dest.seek(SeekFrom::Start(image_size - 1))?;
+        dest.write_all(&[0u8])?;
     }
+
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(dest)?);
+
+    write_root_dir(&mut fs)
 }
 
-pub fn write_data_section<W: Write + Seek>(fat_size: u32, dest: &mut W) {
-    dest.seek(SeekFrom::Start(get_data_section_address(fat_size)))
-        .unwrap();
+pub fn write_root_dir<B: BaseIO>(fs: &mut FileSystem<B>) -> Result<(), FsError> {
+    fs.write_fat_entry(1, EOC)?;
 
-    for _ in 0..get_data_region_size(fat_size) {
-        dest.write_all(&[DATA_REGION]).unwrap();
+    for i in 0..fs.io.entries_per_cluster() {
+        fs.write_directory_entry(1, i, &DirectoryEntry::Invalid)?;
     }
+
+    fs.write_dot_entries(1, 1, 0, 0, 0o755)
 }
 
-pub fn write_root_dir<T: Read + Write + Seek>(fs: &mut FileSystem<T>) {
-    fs.write_fat_entry(1, EOC);
+/// Reserves the last `clusters` clusters of a freshly formatted image for the
+/// metadata journal and records them in the header.
+pub fn create_journal<T: Device>(clusters: u32, dest: &mut T) -> Result<(), FsError> {
+    let mut io = FileSystemBasicIO::open_file_system(&mut *dest)?;
+    let fat_length = io.fat_length();
 
-    for i in 0..(CLUSTER_SIZE / DIR_ENTRY_SIZE) {
-        fs.write_raw_directory_entry(1, i, &DirEntry::from(&DirectoryEntry::Invalid));
+    if clusters < MIN_JOURNAL_CLUSTERS || clusters >= fat_length {
+        return Err(FsError::BadJournal(
+            fat_length.saturating_sub(clusters) + 1,
+            clusters,
+        ));
     }
 
-    fs.write_raw_directory_entry(
-        1,
+    let start = fat_length - clusters + 1;
+
+    if io
+        .read_fat(start, clusters)?
+        .iter()
+        .any(|entry| *entry != FRE)
+    {
+        return Err(FsError::NoSpace);
+    }
+
+    for cluster in start..=fat_length {
+        io.write_fat_entry(cluster, DNA)?;
+    }
+
+    journal::clear(&mut io, start)?;
+
+    dest.seek(SeekFrom::Start(
+        FS_ID.len() as u64 + FS_VERSION.len() as u64 + 8,
+    ))?;
+    dest.write_all(&start.to_le_bytes())?;
+    dest.write_all(&clusters.to_le_bytes())?;
+
+    Ok(dest.flush()?)
+}
+
+/// Number of clusters a file system needs to hold a copy of the host directory
+/// `dir`, including its own root directory.
+pub fn clusters_for_dir(dir: &Path, cluster_size: u32) -> io::Result<u32> {
+    let mut slots = 2;
+    let mut clusters = 0u64;
+
+    for (path, metadata) in host_entries(dir)? {
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            slots += name_slots(name);
+        }
+
+        if metadata.is_dir() {
+            clusters += clusters_for_dir(&path, cluster_size)? as u64;
+        } else if metadata.is_file() {
+            clusters += metadata.len().div_ceil(cluster_size as u64).max(1);
+        }
+    }
+
+    clusters += slots.div_ceil(entries_per_cluster(cluster_size) as u64);
+
+    u32::try_from(clusters).map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))
+}
+
+/// Copies the contents of the host directory `dir` into the root directory,
+/// keeping permissions, ownership and timestamps. Entries that can't be stored
+/// (symlinks, device files, names that aren't UTF-8) are skipped and returned.
+pub fn populate<B: BaseIO>(fs: &mut FileSystem<B>, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut skipped = vec![];
+
+    copy_dir(fs, dir, ROOT, &mut skipped)?;
+    copy_attributes(fs, ROOT, &fs::metadata(dir)?)?;
+
+    Ok(skipped)
+}
+
+fn host_entries(dir: &Path) -> io::Result<Vec<(PathBuf, fs::Metadata)>> {
+    let mut entries = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        entries.push((entry.path(), entry.metadata()?));
+    }
+
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(entries)
+}
+
+fn name_slots(name: &str) -> u64 {
+    let entry = DirectoryEntry::File(Inode::new(
+        name.to_string(),
         0,
-        &DirEntry::from(&DirectoryEntry::Directory(Inode::new(
-            String::from("."),
-            64,
-            0,
-            0,
-            0o755,
-            SystemTime::now(),
-            SystemTime::now(),
-            SystemTime::now(),
-            2,
-            1,
-        ))),
-    );
-
-    fs.write_raw_directory_entry(
-        1,
+        0,
+        0,
+        0,
+        UNIX_EPOCH,
+        UNIX_EPOCH,
+        UNIX_EPOCH,
         1,
-        &DirEntry::from(&DirectoryEntry::Directory(Inode::new(
-            String::from(".."),
-            64,
-            0,
-            0,
-            0o755,
-            SystemTime::now(),
-            SystemTime::now(),
-            SystemTime::now(),
-            2,
-            1,
-        ))),
-    );
+        0,
+    ));
+
+    entry.split().len() as u64
+}
+
+fn with_path(path: &Path, e: impl Into<io::Error>) -> io::Error {
+    let e = e.into();
+
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+fn copy_dir<B: BaseIO>(
+    fs: &mut FileSystem<B>,
+    dir: &Path,
+    (cluster, idx): (FatEntry, u32),
+    skipped: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for (path, metadata) in host_entries(dir).map_err(|e| with_path(dir, e))? {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if metadata.is_dir() || metadata.is_file() => name,
+            _ => {
+                skipped.push(path);
+                continue;
+            }
+        };
+
+        let uid = metadata.uid();
+        let gid = metadata.gid();
+        let permission = (metadata.mode() & 0o7777) as u16;
+
+        let location = if metadata.is_dir() {
+            let location = fs
+                .create_dir(cluster, idx, name, uid, gid, permission)
+                .map_err(|e| with_path(&path, io::Error::from_raw_os_error(e)))?;
+
+            copy_dir(fs, &path, location, skipped)?;
+
+            location
+        } else {
+            let location = fs
+                .create_file(cluster, idx, name, uid, gid, permission)
+                .map_err(|e| with_path(&path, io::Error::from_raw_os_error(e)))?;
+
+            copy_file(fs, &path, location).map_err(|e| with_path(&path, e))?;
+
+            location
+        };
+
+        copy_attributes(fs, location, &metadata).map_err(|e| with_path(&path, e))?;
+    }
+
+    Ok(())
+}
+
+fn copy_file<B: BaseIO>(
+    fs: &mut FileSystem<B>,
+    path: &Path,
+    (cluster, idx): (FatEntry, u32),
+) -> io::Result<()> {
+    let mut entry = fs.read_directory_entry(cluster, idx)?;
+
+    if let DirectoryEntry::File(inode) = &mut entry {
+        let mut file = File::open(path)?;
+        let mut buffer = vec![0u8; WRITE_BUFFER_SIZE as usize];
+        let mut offset = 0;
+
+        loop {
+            let len = file.read(&mut buffer)?;
+
+            if len == 0 {
+                break;
+            }
+
+            fs.write_data(inode, offset, &buffer[..len])?;
+            offset += len as u64;
+        }
+    }
+
+    Ok(fs.write_directory_entry(cluster, idx, &entry)?)
+}
+
+fn copy_attributes<B: BaseIO>(
+    fs: &mut FileSystem<B>,
+    (cluster, idx): (FatEntry, u32),
+    metadata: &fs::Metadata,
+) -> io::Result<()> {
+    let mut entry = fs.read_directory_entry(cluster, idx)?;
+
+    if let DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) = &mut entry {
+        inode.uid = metadata.uid();
+        inode.gid = metadata.gid();
+        inode.permission = (metadata.mode() & 0o7777) as u16;
+        inode.atime = host_time(metadata.atime(), metadata.atime_nsec());
+        inode.mtime = host_time(metadata.mtime(), metadata.mtime_nsec());
+        inode.ctime = host_time(metadata.ctime(), metadata.ctime_nsec());
+    }
+
+    Ok(fs.write_directory_entry(cluster, idx, &entry)?)
+}
+
+fn host_time(secs: i64, nsecs: i64) -> SystemTime {
+    match u64::try_from(secs) {
+        Ok(secs) => UNIX_EPOCH + Duration::new(secs, nsecs as u32),
+        Err(_) => UNIX_EPOCH,
+    }
 }
@@ -1,46 +1,75 @@
+use std::cmp::min;
+
 use crate::{
     consts::{
-        ALIGNMENT, CLUSTER_SIZE, DIR_ENTRY_SIZE, DNA, EOC, FAT_ENTRY_SIZE, FAT_START_ADDR, FRE,
+        ALIGNMENT, DIR_ENTRY_SIZE, DNA, EOC, FAT_ENTRY_SIZE, FAT_START_ADDR, FRE, MAX_CLUSTER_SIZE,
+        MIN_CLUSTER_SIZE,
     },
+    error::FsError,
     FatEntry,
 };
 
 pub fn get_prelude_padding_size(fat_size: FatEntry) -> u64 {
-    (ALIGNMENT - (fat_size * FAT_ENTRY_SIZE % ALIGNMENT)) as u64
+    ALIGNMENT as u64 - (fat_size as u64 * FAT_ENTRY_SIZE as u64 % ALIGNMENT as u64)
 }
 
 pub fn get_data_section_address(fat_size: FatEntry) -> u64 {
-    let used_size = FAT_START_ADDR + (fat_size * FAT_ENTRY_SIZE) as u64;
+    let used_size = FAT_START_ADDR + fat_size as u64 * FAT_ENTRY_SIZE as u64;
     used_size + get_prelude_padding_size(fat_size)
 }
 
-pub fn get_data_region_size(fat_size: FatEntry) -> u64 {
-    (CLUSTER_SIZE * fat_size) as u64
+pub fn get_data_region_size(fat_size: FatEntry, cluster_size: u32) -> u64 {
+    cluster_size as u64 * fat_size as u64
+}
+
+pub fn get_image_size(fat_size: FatEntry, cluster_size: u32) -> u64 {
+    get_data_section_address(fat_size) + get_data_region_size(fat_size, cluster_size)
+}
+
+pub fn get_fat_size_for_image(image_size: u64, cluster_size: u32) -> FatEntry {
+    let per_cluster = (cluster_size + FAT_ENTRY_SIZE) as u64;
+    let mut fat_size = min(image_size / per_cluster, (DNA - 1) as u64) as FatEntry;
+
+    while fat_size > 0 && get_image_size(fat_size, cluster_size) > image_size {
+        fat_size -= 1;
+    }
+
+    fat_size
 }
 
-pub fn check_cluster(fat_length: FatEntry, cluster: FatEntry) -> Result<(), String> {
-    if cluster == FRE {
-        Err("unallocated cluster".to_string())
-    } else if cluster == DNA {
-        Err("bad block".to_string())
-    } else if cluster == EOC {
-        Err("end of chain".to_string())
-    } else if cluster > fat_length {
-        Err(format!("fat out of bounds {:#010X}", cluster))
+pub fn check_cluster(fat_length: FatEntry, cluster: FatEntry) -> Result<(), FsError> {
+    if cluster == EOC {
+        Err(FsError::EndOfChain)
+    } else if cluster == FRE || cluster == DNA || cluster > fat_length {
+        Err(FsError::BadCluster(cluster))
     } else {
         Ok(())
     }
 }
 
-pub fn to_inode(cluster: FatEntry, index: u32) -> u64 {
-    (cluster - 1) as u64 * (CLUSTER_SIZE / DIR_ENTRY_SIZE) as u64 + index as u64 + 1
+pub fn check_cluster_size(cluster_size: u32) -> Result<(), FsError> {
+    if cluster_size.is_power_of_two()
+        && (MIN_CLUSTER_SIZE..=MAX_CLUSTER_SIZE).contains(&cluster_size)
+    {
+        Ok(())
+    } else {
+        Err(FsError::BadClusterSize(cluster_size))
+    }
+}
+
+pub fn entries_per_cluster(cluster_size: u32) -> u32 {
+    cluster_size / DIR_ENTRY_SIZE
+}
+
+pub fn to_inode(cluster: FatEntry, index: u32, entries_per_cluster: u32) -> u64 {
+    (cluster - 1) as u64 * entries_per_cluster as u64 + index as u64 + 1
 }
 
-pub fn from_inode(mut inode: u64) -> (FatEntry, u32) {
+pub fn from_inode(mut inode: u64, entries_per_cluster: u32) -> (FatEntry, u32) {
     inode -= 1;
 
-    let cluster = (inode / (CLUSTER_SIZE / DIR_ENTRY_SIZE) as u64) + 1;
-    let index = inode % (CLUSTER_SIZE / DIR_ENTRY_SIZE) as u64;
+    let cluster = (inode / entries_per_cluster as u64) + 1;
+    let index = inode % entries_per_cluster as u64;
 
     (cluster as FatEntry, index as u32)
 }
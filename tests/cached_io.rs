@@ -0,0 +1,295 @@
+mod common;
+
+use std::{
+    cell::Cell,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+use naths_fat_fs::{
+    consts::EOC,
+    fs::{
+        basic_fs_io::{BaseIO, Device, FileSystemBasicIO},
+        cached_io::{CacheStats, CachedIO},
+        directory::DirectoryEntry,
+        shared_io::SharedIO,
+        FileSystem,
+    },
+};
+
+#[test]
+fn writes_stay_in_cache_until_flushed() {
+    let mut device = common::format_image(16);
+    let before = device.get_ref().clone();
+
+    {
+        let mut cache =
+            CachedIO::new(FileSystemBasicIO::open_file_system(&mut device).unwrap(), 4).unwrap();
+        let cluster_size = cache.cluster_size() as usize;
+
+        cache.write_fat_entry(5, EOC).unwrap();
+        cache.write_cluster(5, &vec![0x42; cluster_size]).unwrap();
+        cache.write_raw_directory_entry(1, 3, &[0x11; 64]).unwrap();
+
+        assert_eq!(cache.read_fat_entry(5).unwrap(), EOC);
+        assert_eq!(cache.read_raw_directory_entry(1, 3).unwrap(), [0x11; 64]);
+        assert_eq!(cache.dirty(), 3);
+        assert_eq!(cache.get_ref().device.get_ref(), &before);
+
+        cache.flush().unwrap();
+        assert_eq!(cache.dirty(), 0);
+        assert_ne!(cache.get_ref().device.get_ref(), &before);
+    }
+
+    let mut io = FileSystemBasicIO::open_file_system(&mut device).unwrap();
+    let cluster_size = io.cluster_size() as usize;
+
+    assert_eq!(io.read_fat_entry(5).unwrap(), EOC);
+    assert_eq!(io.read_cluster(5).unwrap(), vec![0x42; cluster_size]);
+    assert_eq!(io.read_raw_directory_entry(1, 3).unwrap(), [0x11; 64]);
+}
+
+#[test]
+fn least_recently_used_clusters_are_evicted() {
+    let mut device = common::format_image(16);
+    let mut cache =
+        CachedIO::new(FileSystemBasicIO::open_file_system(&mut device).unwrap(), 2).unwrap();
+    let cluster_size = cache.cluster_size() as usize;
+
+    cache.read_cluster(1).unwrap();
+    cache.read_cluster(2).unwrap();
+    cache.read_cluster(1).unwrap();
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
+
+    cache.write_cluster(3, &vec![7; cluster_size]).unwrap();
+    assert_eq!(cache.dirty(), 1);
+
+    cache.read_cluster(1).unwrap();
+    cache.read_cluster(2).unwrap();
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 3 });
+
+    // cluster 3 was written back when it got evicted
+    cache.read_cluster(1).unwrap();
+    assert_eq!(cache.dirty(), 0);
+    assert_eq!(cache.read_cluster(3).unwrap(), vec![7; cluster_size]);
+}
+
+/// Remembers what was on the device when it was last synced.
+struct Synced {
+    inner: Cursor<Vec<u8>>,
+    synced: Vec<u8>,
+}
+
+impl Read for Synced {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for Synced {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Synced {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Device for Synced {
+    fn sync_data(&mut self) -> io::Result<()> {
+        self.synced = self.inner.get_ref().clone();
+        Ok(())
+    }
+}
+
+#[test]
+fn sync_writes_back_and_reaches_the_device() {
+    let device = common::format_image(16);
+    let formatted = device.get_ref().clone();
+    let mut device = Synced {
+        synced: formatted.clone(),
+        inner: device,
+    };
+
+    let mut cache =
+        CachedIO::new(FileSystemBasicIO::open_file_system(&mut device).unwrap(), 4).unwrap();
+
+    cache.write_fat_entry(5, EOC).unwrap();
+    cache.flush().unwrap();
+    assert_eq!(cache.get_ref().device.synced, formatted);
+
+    cache.sync().unwrap();
+    assert_eq!(cache.dirty(), 0);
+    drop(cache);
+
+    let mut io = FileSystemBasicIO::open_file_system(Cursor::new(device.synced)).unwrap();
+    assert_eq!(io.read_fat_entry(5).unwrap(), EOC);
+}
+
+/// Refuses every write while `broken` is set.
+struct Failing {
+    inner: Cursor<Vec<u8>>,
+    broken: Rc<Cell<bool>>,
+}
+
+impl Read for Failing {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for Failing {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.broken.get() {
+            return Err(io::Error::other("broken"));
+        }
+
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Failing {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Device for Failing {
+    fn sync_data(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_eviction_keeps_the_dirty_page() {
+    let broken = Rc::new(Cell::new(false));
+    let device = Failing {
+        inner: common::format_image(16),
+        broken: broken.clone(),
+    };
+
+    let mut cache = CachedIO::new(FileSystemBasicIO::open_file_system(device).unwrap(), 1).unwrap();
+    let cluster_size = cache.cluster_size() as usize;
+
+    cache.write_cluster(5, &vec![0x42; cluster_size]).unwrap();
+
+    broken.set(true);
+    assert!(cache.read_cluster(6).is_err());
+    assert_eq!(cache.dirty(), 1);
+    assert_eq!(cache.read_cluster(5).unwrap(), vec![0x42; cluster_size]);
+
+    broken.set(false);
+    let mut io = cache.into_inner().unwrap();
+    assert_eq!(io.read_cluster(5).unwrap(), vec![0x42; cluster_size]);
+}
+
+#[test]
+fn file_system_runs_on_top_of_the_cache() {
+    let mut device = common::format_image(16);
+
+    {
+        let io = FileSystemBasicIO::open_file_system(&mut device).unwrap();
+        let mut fs = FileSystem::new(CachedIO::new(io, 8).unwrap());
+
+        let (c, i) = fs.create_file(1, 0, "cached", 0, 0, 0o644).unwrap();
+        let mut entry = fs.read_directory_entry(c, i).unwrap();
+
+        if let DirectoryEntry::File(inode) = &mut entry {
+            fs.write_data(inode, 0, b"written through the cache")
+                .unwrap();
+        }
+
+        fs.write_directory_entry(c, i, &entry).unwrap();
+        assert!(fs.io.stats().hits > 0);
+    }
+
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let root = fs.get_chain(1).unwrap();
+
+    match fs.find_in_dir(&root, "cached").unwrap() {
+        Some((DirectoryEntry::File(inode), _, _)) => assert_eq!(
+            fs.read_data(&inode, 0, 100).unwrap(),
+            b"written through the cache"
+        ),
+        e => panic!("unexpected entry {:?}", e),
+    }
+}
+
+#[test]
+fn layers_hand_back_the_device() {
+    let device = common::format_image(16);
+
+    let io = SharedIO::new(
+        CachedIO::new(FileSystemBasicIO::open_file_system(device).unwrap(), 4).unwrap(),
+    );
+    let mut fs = FileSystem::new(io.clone());
+
+    fs.create_file(1, 0, "file", 0, 0, 0o644).unwrap();
+
+    let io = match io.try_into_inner() {
+        Ok(_) => panic!("file system still holds a handle"),
+        Err(io) => io,
+    };
+    drop(fs);
+
+    let device = io
+        .try_into_inner()
+        .ok()
+        .unwrap()
+        .into_inner()
+        .unwrap()
+        .into_inner();
+
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(device).unwrap());
+    let root = fs.get_chain(1).unwrap();
+
+    assert!(fs.find_in_dir(&root, "file").unwrap().is_some());
+}
+
+#[test]
+fn cached_io_into_inner_writes_back() {
+    let device = common::format_image(16);
+
+    let mut cache = CachedIO::new(FileSystemBasicIO::open_file_system(device).unwrap(), 4).unwrap();
+    let cluster_size = cache.cluster_size() as usize;
+
+    cache.write_fat_entry(5, EOC).unwrap();
+    cache.write_cluster(5, &vec![0x42; cluster_size]).unwrap();
+    assert_eq!(cache.dirty(), 2);
+
+    let mut io = cache.into_inner().unwrap();
+
+    assert_eq!(io.read_fat_entry(5).unwrap(), EOC);
+    assert_eq!(io.read_cluster(5).unwrap(), vec![0x42; cluster_size]);
+}
+
+#[test]
+fn shared_io_is_only_handed_back_by_the_last_handle() {
+    let device = common::format_image(16);
+
+    let first = SharedIO::new(FileSystemBasicIO::open_file_system(device).unwrap());
+    let mut second = first.clone();
+
+    let mut first = match first.try_into_inner() {
+        Ok(_) => panic!("another handle is still alive"),
+        Err(io) => io,
+    };
+
+    second.write_fat_entry(5, EOC).unwrap();
+    assert_eq!(first.read_fat_entry(5).unwrap(), EOC);
+    drop(second);
+
+    let mut io = first.try_into_inner().ok().unwrap();
+    assert_eq!(io.read_fat_entry(5).unwrap(), EOC);
+}
@@ -0,0 +1,82 @@
+// not every test uses every helper
+#![allow(dead_code)]
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::Cursor,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+};
+
+use naths_fat_fs::{
+    consts::DEFAULT_CLUSTER_SIZE,
+    fs::{
+        basic_fs_io::{BaseIO, FileSystemBasicIO},
+        directory::DirectoryEntry,
+        volume::Volume,
+        FileSystem,
+    },
+    mkfs::format,
+    FatEntry,
+};
+
+pub fn format_image(fat_size: u32) -> Cursor<Vec<u8>> {
+    format_image_with(fat_size, DEFAULT_CLUSTER_SIZE)
+}
+
+pub fn format_image_with(fat_size: u32, cluster_size: u32) -> Cursor<Vec<u8>> {
+    let mut device = Cursor::new(vec![]);
+
+    format(fat_size, cluster_size, &mut device).unwrap();
+
+    device
+}
+
+/// Opens an image file for reading and writing.
+pub fn open_image(image: &Path) -> Volume<FileSystemBasicIO<File>> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(image)
+        .unwrap();
+
+    Volume::new(FileSystemBasicIO::open_file_system(device).unwrap())
+}
+
+pub fn start_cluster<B: BaseIO>(fs: &mut FileSystem<B>, cluster: FatEntry, idx: u32) -> FatEntry {
+    match fs.read_directory_entry(cluster, idx).unwrap() {
+        DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) => inode.start_cluster,
+        e => panic!("unexpected entry {:?}", e),
+    }
+}
+
+/// Fresh directory below the system temp dir that is removed again when dropped,
+/// even if the test fails. Leftovers from an earlier run are removed first.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("nathfat-{}-{}", name, process::id()));
+
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    process::{Command, Output},
+};
+
+use naths_fat_fs::{
+    consts::{DEFAULT_CLUSTER_SIZE, EOC},
+    fs::{
+        basic_fs_io::BaseIO,
+        journal::{self, Transaction},
+    },
+    mkfs::{create_journal, format},
+};
+
+fn debugfs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_debugfs-nathfat"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(args: &[&str]) -> String {
+    let output = debugfs(args);
+
+    assert!(
+        output.status.success(),
+        "debugfs {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn image(tmp: &common::TempDir) -> String {
+    let image = tmp.join("disk.img");
+
+    format(
+        32,
+        DEFAULT_CLUSTER_SIZE,
+        &mut File::create_new(&image).unwrap(),
+    )
+    .unwrap();
+
+    image.to_str().unwrap().to_string()
+}
+
+#[test]
+fn fat_chain_and_entry_requests() {
+    let tmp = common::TempDir::new("debugfs-read");
+    let image = image(&tmp);
+
+    let (cluster, idx) = {
+        let mut volume = common::open_image(Path::new(&image));
+        volume
+            .create("/file.txt")
+            .unwrap()
+            .write_all(&vec![1; DEFAULT_CLUSTER_SIZE as usize * 2])
+            .unwrap();
+        volume.lookup("/file.txt").unwrap()
+    };
+
+    assert_eq!(stdout(&[&image, "-R", "fat 1"]), "0x00000001: EOC\n");
+
+    let entry = stdout(&[&image, "-R", &format!("entry {} {}", cluster, idx)]);
+    assert!(entry.contains("\"file.txt\""), "{}", entry);
+    assert!(
+        entry.contains(&format!("length={} ", DEFAULT_CLUSTER_SIZE * 2)),
+        "{}",
+        entry
+    );
+
+    let start = entry
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("start="))
+        .unwrap();
+    let chain = stdout(&[&image, "-R", &format!("chain {}", start)]);
+    assert!(chain.ends_with(" -> EOC\n2 clusters\n"), "{}", chain);
+
+    assert!(!debugfs(&[&image, "-R", "fat 0"]).status.success());
+    assert!(!debugfs(&[&image, "-R", "chain 1000"]).status.success());
+}
+
+#[test]
+fn set_fat_is_guarded() {
+    let tmp = common::TempDir::new("debugfs-set-fat");
+    let image = image(&tmp);
+
+    let output = debugfs(&[&image, "-R", "set_fat 5 eoc"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use -w"));
+
+    let output = debugfs(&[&image, "-w", "-R", "set_fat 5 0x1000"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use -f"));
+
+    assert_eq!(
+        common::open_image(Path::new(&image))
+            .fs
+            .io
+            .read_fat_entry(5)
+            .unwrap(),
+        0
+    );
+
+    assert_eq!(
+        stdout(&[&image, "-w", "-R", "set_fat 5 eoc"]),
+        "0x00000005: free -> EOC\n"
+    );
+    stdout(&[&image, "-w", "-R", "set_fat 6 0x1000 -f"]);
+
+    let mut volume = common::open_image(Path::new(&image));
+    assert_eq!(volume.fs.io.read_fat_entry(5).unwrap(), EOC);
+    assert_eq!(volume.fs.io.read_fat_entry(6).unwrap(), 0x1000);
+}
+
+#[test]
+fn renaming_an_entry_drops_its_long_name() {
+    let tmp = common::TempDir::new("debugfs-name");
+    let image = image(&tmp);
+    let long = "a name much longer than an inode can hold.txt";
+
+    let (cluster, idx) = {
+        let mut volume = common::open_image(Path::new(&image));
+        volume.create(&format!("/{}", long)).unwrap();
+        volume.lookup(&format!("/{}", long)).unwrap()
+    };
+
+    let output = stdout(&[
+        &image,
+        "-w",
+        "-R",
+        &format!("set_entry {} {} name short.txt", cluster, idx),
+    ]);
+    assert!(output.contains("cleared"), "{}", output);
+
+    let mut volume = common::open_image(Path::new(&image));
+    let names: Vec<String> = volume
+        .read_dir("/")
+        .unwrap()
+        .into_iter()
+        .map(|metadata| metadata.name)
+        .collect();
+
+    assert_eq!(names, vec!["short.txt"]);
+    assert_eq!(volume.lookup("/short.txt").unwrap(), (cluster, idx));
+}
+
+#[test]
+fn pending_journal_is_shown_without_replaying_it() {
+    let tmp = common::TempDir::new("debugfs-journal");
+    let image = image(&tmp);
+
+    create_journal(
+        4,
+        &mut OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&image)
+            .unwrap(),
+    )
+    .unwrap();
+
+    {
+        let mut volume = common::open_image(Path::new(&image));
+        volume.create("/f").unwrap();
+
+        // leave a transaction in the journal as if the machine died before applying it
+        let mut io = volume.into_inner().into_inner();
+        let (start, length) = io.journal().unwrap();
+
+        let mut transaction = Transaction::default();
+        transaction.fat.insert(5, EOC);
+        assert!(journal::log(&mut io, start, length, &transaction).unwrap());
+    }
+
+    let before = fs::read(&image).unwrap();
+
+    assert_eq!(
+        stdout(&[&image, "-R", "journal"]),
+        "fat   0x00000005: EOC\n"
+    );
+    assert_eq!(stdout(&[&image, "-R", "fat 5"]), "0x00000005: EOC\n");
+    assert!(stdout(&[&image, "-R", "lookup /f"]).starts_with("/f: "));
+    assert_eq!(fs::read(&image).unwrap(), before);
+
+    stdout(&[&image, "-w", "-R", "header"]);
+    assert_eq!(
+        stdout(&[&image, "-R", "journal"]),
+        "no transaction pending\n"
+    );
+    assert_eq!(
+        common::open_image(Path::new(&image))
+            .fs
+            .io
+            .read_fat_entry(5)
+            .unwrap(),
+        EOC
+    );
+}
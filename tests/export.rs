@@ -0,0 +1,113 @@
+mod common;
+
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use naths_fat_fs::{
+    export::export,
+    fs::{basic_fs_io::FileSystemBasicIO, directory::DirectoryEntry, volume::Volume},
+};
+
+#[test]
+fn export_restores_tree_and_skips_corrupt_entries() {
+    let dest = common::TempDir::new("export");
+    let device = common::format_image(32);
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(device).unwrap());
+
+    let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+
+    volume.create_dir("/dir").unwrap();
+    volume.create_dir("/dir/sub").unwrap();
+    volume
+        .create("/dir/sub/data")
+        .unwrap()
+        .write_all(&data)
+        .unwrap();
+    volume
+        .create("/dir/broken")
+        .unwrap()
+        .write_all(b"x")
+        .unwrap();
+    volume.create("/top").unwrap();
+
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+
+    for (path, permission) in [("/dir/sub/data", 0o600), ("/dir", 0o750)] {
+        let (c, i) = volume.lookup(path).unwrap();
+        let mut entry = volume.fs.read_directory_entry(c, i).unwrap();
+        if let DirectoryEntry::Directory(inode) | DirectoryEntry::File(inode) = &mut entry {
+            inode.permission = permission;
+            inode.mtime = mtime;
+        }
+        volume.fs.write_directory_entry(c, i, &entry).unwrap();
+    }
+
+    let (c, i) = volume.lookup("/dir/broken").unwrap();
+    let mut entry = volume.fs.read_directory_entry(c, i).unwrap();
+    if let DirectoryEntry::File(inode) = &mut entry {
+        inode.start_cluster = 0x1234;
+    }
+    volume.fs.write_directory_entry(c, i, &entry).unwrap();
+
+    let (c, i) = volume.lookup("/dir").unwrap();
+    let skipped = export(&mut volume.fs, c, i, &dest).unwrap();
+
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].path, "/broken");
+
+    assert_eq!(fs::read(dest.join("sub/data")).unwrap(), data);
+    assert!(!dest.join("broken").exists());
+    assert!(!dest.join("top").exists());
+
+    let meta = fs::metadata(dest.join("sub/data")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o600);
+    assert_eq!(meta.modified().unwrap(), mtime);
+
+    fs::remove_dir_all(&*dest).unwrap();
+
+    let skipped = export(&mut volume.fs, 1, 0, &dest).unwrap();
+
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].path, "/dir/broken");
+    assert!(dest.join("top").is_file());
+
+    let meta = fs::metadata(dest.join("dir")).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o750);
+    assert_eq!(meta.modified().unwrap(), mtime);
+}
+
+#[test]
+fn names_that_leave_the_destination_are_skipped() {
+    let tmp = common::TempDir::new("export-escape");
+    let dest = tmp.join("dest");
+    let device = common::format_image(32);
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(device).unwrap());
+
+    let (c, i) = volume
+        .fs
+        .create_file(1, 0, "../escaped", 0, 0, 0o644)
+        .unwrap();
+    let mut file = volume.fs.read_directory_entry(c, i).unwrap();
+    if let DirectoryEntry::File(inode) = &mut file {
+        volume.fs.write_data(inode, 0, b"pwned").unwrap();
+    }
+    volume.fs.write_directory_entry(c, i, &file).unwrap();
+
+    let (c, i) = volume.fs.create_dir(1, 0, "a/..", 0, 0, 0o755).unwrap();
+    volume.fs.create_file(c, i, "inside", 0, 0, 0o644).unwrap();
+    volume.create("/fine").unwrap();
+
+    let skipped = export(&mut volume.fs, 1, 0, &dest).unwrap();
+    let mut paths: Vec<_> = skipped.iter().map(|e| e.path.as_str()).collect();
+    paths.sort();
+
+    assert_eq!(paths, ["/../escaped", "/a/.."]);
+    assert!(!tmp.join("escaped").exists());
+    assert!(!dest.join("a").exists());
+    assert!(!tmp.join("inside").exists());
+    assert!(dest.join("fine").is_file());
+}
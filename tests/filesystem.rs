@@ -0,0 +1,558 @@
+mod common;
+
+use naths_fat_fs::{
+    consts::{DIR_ENTRY_SIZE, DNA, EOC, FRE},
+    error::FsError,
+    fs::{
+        basic_fs_io::{BaseIO, FileSystemBasicIO},
+        directory::{DirectoryEntry, Inode},
+        FileSystem,
+    },
+    DirEntry,
+};
+use std::time::SystemTime;
+
+#[test]
+fn read_data_spanning_clusters() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    let content: Vec<u8> = (0..(cluster_size * 2 + 100)).map(|i| i as u8).collect();
+
+    let mut chain = vec![];
+
+    for chunk in content.chunks(cluster_size as usize) {
+        let cluster = fs.append_to_chain(&mut chain).unwrap();
+        let mut block = fs.read_cluster(cluster).unwrap();
+        block[0..chunk.len()].copy_from_slice(chunk);
+        fs.write_cluster(cluster, &block).unwrap();
+    }
+
+    let inode = Inode::new(
+        "data.bin".to_string(),
+        content.len() as u64,
+        1000,
+        1000,
+        0o644,
+        SystemTime::now(),
+        SystemTime::now(),
+        SystemTime::now(),
+        1,
+        chain[0],
+    );
+
+    assert_eq!(fs.read_data(&inode, 0, u32::MAX).unwrap(), content);
+
+    let offset = cluster_size as u64 - 10;
+    assert_eq!(
+        fs.read_data(&inode, offset, cluster_size + 20).unwrap(),
+        content[offset as usize..offset as usize + cluster_size as usize + 20]
+    );
+
+    assert_eq!(
+        fs.read_data(&inode, content.len() as u64 - 5, 100)
+            .unwrap()
+            .len(),
+        5
+    );
+    assert!(fs
+        .read_data(&inode, content.len() as u64, 100)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn write_data_extends_chain() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    let start = fs.alloc_chunk().unwrap();
+    let mut inode = Inode::new(
+        "data.bin".to_string(),
+        0,
+        1000,
+        1000,
+        0o644,
+        SystemTime::now(),
+        SystemTime::now(),
+        SystemTime::now(),
+        1,
+        start,
+    );
+
+    let head = b"head";
+    let tail = vec![0x42u8; cluster_size as usize];
+    let tail_offset = cluster_size as u64 + 10;
+
+    assert_eq!(
+        fs.write_data(&mut inode, 0, head).unwrap(),
+        head.len() as u32
+    );
+    assert_eq!(
+        fs.write_data(&mut inode, tail_offset, &tail).unwrap(),
+        tail.len() as u32
+    );
+
+    assert_eq!(inode.length, tail_offset + tail.len() as u64);
+    assert_eq!(fs.get_chain(start).unwrap().len(), 3);
+
+    let content = fs.read_data(&inode, 0, u32::MAX).unwrap();
+    assert_eq!(&content[0..4], head);
+    assert!(content[4..tail_offset as usize].iter().all(|b| *b == 0));
+    assert_eq!(&content[tail_offset as usize..], &tail[..]);
+}
+
+#[test]
+fn create_file_in_root() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    let (c, i) = fs.create_file(1, 0, "new.txt", 1000, 100, 0o640).unwrap();
+
+    match fs.read_directory_entry(c, i).unwrap() {
+        DirectoryEntry::File(inode) => {
+            assert_eq!(inode.name, "new.txt");
+            assert_eq!(inode.length, 0);
+            assert_eq!((inode.uid, inode.gid, inode.permission), (1000, 100, 0o640));
+            assert_eq!(fs.get_chain(inode.start_cluster).unwrap().len(), 1);
+        }
+        e => panic!("unexpected entry {:?}", e),
+    }
+
+    let root = fs.get_chain(1).unwrap();
+    assert!(fs.find_in_dir(&root, "new.txt").unwrap().is_some());
+    assert_eq!(
+        fs.create_file(1, 0, "new.txt", 1000, 100, 0o640),
+        Err(libc::EEXIST)
+    );
+}
+
+#[test]
+fn create_file_grows_directory() {
+    let mut device = common::format_image(128);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    let per_cluster = cluster_size / DIR_ENTRY_SIZE;
+
+    for n in 0..per_cluster {
+        fs.create_file(1, 0, &format!("file{}", n), 0, 0, 0o644)
+            .unwrap();
+    }
+
+    let root = fs.get_chain(1).unwrap();
+    assert_eq!(root.len(), 2);
+
+    for n in 0..per_cluster {
+        assert!(fs
+            .find_in_dir(&root, &format!("file{}", n))
+            .unwrap()
+            .is_some());
+    }
+}
+
+#[test]
+fn create_and_remove_dir() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    let (c, i) = fs.create_dir(1, 0, "sub", 1000, 1000, 0o755).unwrap();
+
+    let sub = match fs.read_directory_entry(c, i).unwrap() {
+        DirectoryEntry::Directory(inode) => inode,
+        e => panic!("unexpected entry {:?}", e),
+    };
+
+    let chain = fs.dir_chain(c, i).unwrap();
+
+    match fs.find_in_dir(&chain, ".").unwrap() {
+        Some((DirectoryEntry::Directory(dot), _, _)) => {
+            assert_eq!(dot.start_cluster, sub.start_cluster)
+        }
+        e => panic!("unexpected entry {:?}", e),
+    }
+
+    match fs.find_in_dir(&chain, "..").unwrap() {
+        Some((DirectoryEntry::Directory(dotdot), _, _)) => assert_eq!(dotdot.start_cluster, 1),
+        e => panic!("unexpected entry {:?}", e),
+    }
+
+    fs.create_file(c, i, "file", 1000, 1000, 0o644).unwrap();
+    assert_eq!(fs.remove_dir(1, 0, "sub"), Err(libc::ENOTEMPTY));
+
+    assert_eq!(fs.remove_file(c, i, "file"), Ok(()));
+
+    assert_eq!(fs.remove_dir(1, 0, "sub"), Ok(()));
+    assert_eq!(fs.read_fat_entry(sub.start_cluster).unwrap(), FRE);
+    let root = fs.get_chain(1).unwrap();
+    assert!(fs.find_in_dir(&root, "sub").unwrap().is_none());
+}
+
+#[test]
+fn remove_file_frees_chain_and_long_name() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    let (c, i) = fs.create_file(1, 0, "file", 1000, 1000, 0o644).unwrap();
+
+    let mut inode = match fs.read_directory_entry(c, i).unwrap() {
+        DirectoryEntry::File(inode) => inode,
+        e => panic!("unexpected entry {:?}", e),
+    };
+
+    fs.write_data(&mut inode, 0, &vec![1u8; cluster_size as usize * 2])
+        .unwrap();
+    let chain = fs.get_chain(inode.start_cluster).unwrap();
+
+    fs.write_raw_directory_entry(c, i, &DirEntry::from(&DirectoryEntry::Invalid))
+        .unwrap();
+    fs.write_raw_directory_entry(
+        c,
+        i,
+        &DirEntry::from(&DirectoryEntry::LongFileName("l".repeat(63))),
+    )
+    .unwrap();
+    fs.write_raw_directory_entry(c, i + 1, &DirEntry::from(&DirectoryEntry::File(inode)))
+        .unwrap();
+
+    let name = format!("{}file", "l".repeat(63));
+    assert_eq!(fs.remove_file(1, 0, &name), Ok(()));
+
+    for cluster in chain {
+        assert_eq!(fs.read_fat_entry(cluster).unwrap(), FRE);
+    }
+
+    for idx in [i, i + 1] {
+        assert!(matches!(
+            fs.read_directory_entry(c, idx).unwrap(),
+            DirectoryEntry::Invalid
+        ));
+    }
+
+    assert_eq!(fs.remove_file(1, 0, &name), Err(libc::ENOENT));
+}
+
+#[test]
+fn rename_moves_entries_and_keeps_inodes() {
+    let mut device = common::format_image(32);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    let (fc, fi) = fs.create_file(1, 0, "file", 0, 0, 0o644).unwrap();
+    let (dc, di) = fs.create_dir(1, 0, "dir", 0, 0, 0o755).unwrap();
+    let (sc, si) = fs.create_dir(1, 0, "sub", 0, 0, 0o755).unwrap();
+
+    let file_inode = fs.inodes.inode(fc, fi);
+    let file_start = common::start_cluster(&mut fs, fc, fi);
+    let sub_inode = fs.inodes.inode(sc, si);
+    let sub_start = common::start_cluster(&mut fs, sc, si);
+    let dir_start = common::start_cluster(&mut fs, dc, di);
+
+    assert_eq!(fs.rename(1, 0, "file", 1, 0, "renamed", 0), Ok(()));
+    let (rc, ri) = fs.inodes.resolve(file_inode);
+    assert_eq!(common::start_cluster(&mut fs, rc, ri), file_start);
+
+    let root = fs.get_chain(1).unwrap();
+    assert!(fs.find_in_dir(&root, "file").unwrap().is_none());
+    assert!(fs.find_in_dir(&root, "renamed").unwrap().is_some());
+
+    let new_inode = fs.create_file(1, 0, "other", 0, 0, 0o644).unwrap();
+    assert_ne!(fs.inodes.inode(new_inode.0, new_inode.1), file_inode);
+
+    assert_eq!(fs.rename(1, 0, "sub", dc, di, "moved", 0), Ok(()));
+    let (mc, mi) = fs.inodes.resolve(sub_inode);
+    assert_eq!(common::start_cluster(&mut fs, mc, mi), sub_start);
+
+    let moved = fs.get_chain(sub_start).unwrap();
+    match fs.find_in_dir(&moved, "..").unwrap() {
+        Some((DirectoryEntry::Directory(inode), _, _)) => {
+            assert_eq!(inode.start_cluster, dir_start)
+        }
+        e => panic!("unexpected entry {:?}", e),
+    }
+
+    assert_eq!(fs.rename(1, 0, "dir", mc, mi, "loop", 0), Err(libc::EINVAL));
+}
+
+#[test]
+fn rename_replace_noreplace_and_exchange() {
+    let mut device = common::format_image(32);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    let (ac, ai) = fs.create_file(1, 0, "a", 0, 0, 0o644).unwrap();
+    let (bc, bi) = fs.create_file(1, 0, "b", 0, 0, 0o644).unwrap();
+    let a_start = common::start_cluster(&mut fs, ac, ai);
+    let b_start = common::start_cluster(&mut fs, bc, bi);
+    let a_inode = fs.inodes.inode(ac, ai);
+    let b_inode = fs.inodes.inode(bc, bi);
+
+    assert_eq!(
+        fs.rename(1, 0, "a", 1, 0, "b", libc::RENAME_NOREPLACE),
+        Err(libc::EEXIST)
+    );
+
+    assert_eq!(
+        fs.rename(1, 0, "a", 1, 0, "b", libc::RENAME_EXCHANGE),
+        Ok(())
+    );
+
+    let root = fs.get_chain(1).unwrap();
+    match fs.find_in_dir(&root, "a").unwrap() {
+        Some((DirectoryEntry::File(inode), _, _)) => assert_eq!(inode.start_cluster, b_start),
+        e => panic!("unexpected entry {:?}", e),
+    }
+
+    let (c, i) = fs.inodes.resolve(a_inode);
+    assert_eq!(common::start_cluster(&mut fs, c, i), a_start);
+    let (c, i) = fs.inodes.resolve(b_inode);
+    assert_eq!(common::start_cluster(&mut fs, c, i), b_start);
+
+    assert_eq!(fs.rename(1, 0, "a", 1, 0, "b", 0), Ok(()));
+
+    let root = fs.get_chain(1).unwrap();
+    assert!(fs.find_in_dir(&root, "a").unwrap().is_none());
+    assert_eq!(fs.read_fat_entry(a_start).unwrap(), FRE);
+    match fs.find_in_dir(&root, "b").unwrap() {
+        Some((DirectoryEntry::File(inode), _, _)) => assert_eq!(inode.start_cluster, b_start),
+        e => panic!("unexpected entry {:?}", e),
+    }
+}
+
+#[test]
+fn truncate_frees_and_zero_fills() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    let start = fs.alloc_chunk().unwrap();
+    let mut inode = Inode::new(
+        "data.bin".to_string(),
+        0,
+        0,
+        0,
+        0o644,
+        SystemTime::now(),
+        SystemTime::now(),
+        SystemTime::now(),
+        1,
+        start,
+    );
+
+    fs.write_data(&mut inode, 0, &vec![0xFFu8; cluster_size as usize * 3])
+        .unwrap();
+    let chain = fs.get_chain(start).unwrap();
+    assert_eq!(chain.len(), 3);
+
+    fs.truncate(&mut inode, 10).unwrap();
+    assert_eq!(inode.length, 10);
+    assert_eq!(fs.get_chain(start).unwrap(), chain[0..1]);
+    assert_eq!(fs.read_fat_entry(chain[1]).unwrap(), FRE);
+    assert_eq!(fs.read_fat_entry(chain[2]).unwrap(), FRE);
+
+    fs.truncate(&mut inode, cluster_size as u64 + 10).unwrap();
+    assert_eq!(fs.get_chain(start).unwrap().len(), 2);
+
+    let content = fs.read_data(&inode, 0, u32::MAX).unwrap();
+    assert_eq!(content.len(), cluster_size as usize + 10);
+    assert!(content[0..10].iter().all(|b| *b == 0xFF));
+    assert!(content[10..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn long_names_round_trip() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    let names = [
+        "exactly-twenty-five-bytes".to_string(),
+        "a-name-that-is-longer-than-twenty-five-bytes.txt".to_string(),
+        "ü".repeat(100),
+        format!("{}🦀.rs", "x".repeat(61)),
+    ];
+
+    for name in &names {
+        let entry = DirectoryEntry::File(Inode::new(
+            name.clone(),
+            0,
+            0,
+            0,
+            0o644,
+            SystemTime::now(),
+            SystemTime::now(),
+            SystemTime::now(),
+            1,
+            1,
+        ));
+
+        assert_eq!(entry.split().len() > 1, name.len() > 25);
+
+        fs.create_file(1, 0, name, 0, 0, 0o644).unwrap();
+    }
+
+    let root = fs.get_chain(1).unwrap();
+
+    for name in &names {
+        match fs.find_in_dir(&root, name).unwrap() {
+            Some((DirectoryEntry::File(inode), _, _)) => assert_eq!(&inode.name, name),
+            e => panic!("unexpected entry {:?}", e),
+        }
+    }
+
+    assert_eq!(fs.remove_file(1, 0, &names[2]), Ok(()));
+    assert_eq!(fs.rename(1, 0, &names[1], 1, 0, &names[2], 0), Ok(()));
+
+    let root = fs.get_chain(1).unwrap();
+    assert!(fs.find_in_dir(&root, &names[1]).unwrap().is_none());
+    assert!(fs.find_in_dir(&root, &names[2]).unwrap().is_some());
+    assert!(fs.find_in_dir(&root, &names[3]).unwrap().is_some());
+}
+
+#[test]
+fn corruption_is_reported_as_errors() {
+    let mut garbage = std::io::Cursor::new(vec![0u8; 64]);
+    assert!(matches!(
+        FileSystemBasicIO::open_file_system(&mut garbage),
+        Err(FsError::BadMagic(_))
+    ));
+
+    let mut device = common::format_image(4);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    let (c, i) = fs.create_file(1, 0, "file", 0, 0, 0o644).unwrap();
+    let start = match fs.read_directory_entry(c, i).unwrap() {
+        DirectoryEntry::File(inode) => inode.start_cluster,
+        e => panic!("unexpected entry {:?}", e),
+    };
+
+    fs.write_fat_entry(start, 0x1234).unwrap();
+    assert!(matches!(
+        fs.get_chain(start),
+        Err(FsError::BadCluster(0x1234))
+    ));
+    assert_eq!(fs.remove_file(1, 0, "file"), Err(libc::EUCLEAN));
+
+    fs.write_fat_entry(start, EOC).unwrap();
+    while fs.alloc_chunk().is_ok() {}
+    assert!(matches!(fs.alloc_chunk(), Err(FsError::NoSpace)));
+
+    let mut raw = fs.read_raw_directory_entry(c, i).unwrap();
+    raw[39] = 0xFF;
+    fs.write_raw_directory_entry(c, i, &raw).unwrap();
+    assert!(matches!(
+        fs.read_directory_entry(c, i),
+        Err(FsError::BadName(_))
+    ));
+}
+
+#[test]
+fn free_cluster_count_is_kept_current() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    assert_eq!(fs.free_clusters().unwrap(), 15);
+
+    fs.write_fat_entry(16, DNA).unwrap();
+    let (c, i) = fs.create_file(1, 0, "file", 0, 0, 0o644).unwrap();
+    let mut inode = match fs.read_directory_entry(c, i).unwrap() {
+        DirectoryEntry::File(inode) => inode,
+        e => panic!("unexpected entry {:?}", e),
+    };
+    fs.write_data(&mut inode, 0, &vec![0u8; cluster_size as usize * 2])
+        .unwrap();
+    fs.write_directory_entry(c, i, &DirectoryEntry::File(inode))
+        .unwrap();
+    assert_eq!(fs.free_clusters().unwrap(), 12);
+
+    fs.remove_file(1, 0, "file").unwrap();
+    assert_eq!(fs.free_clusters().unwrap(), 14);
+
+    drop(fs);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    assert_eq!(fs.free_clusters().unwrap(), 14);
+}
+
+#[test]
+fn used_entry_count_is_kept_current() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    fs.create_file(1, 0, "a", 0, 0, 0o644).unwrap();
+    assert_eq!(fs.used_entries().unwrap(), 2);
+
+    let (c, i) = fs.create_dir(1, 0, "dir", 0, 0, 0o755).unwrap();
+    fs.create_file(c, i, "b", 0, 0, 0o644).unwrap();
+    assert_eq!(fs.used_entries().unwrap(), 4);
+
+    fs.rename(1, 0, "a", c, i, "b", 0).unwrap();
+    assert_eq!(fs.used_entries().unwrap(), 3);
+
+    fs.remove_file(c, i, "b").unwrap();
+    fs.remove_dir(1, 0, "dir").unwrap();
+    assert_eq!(fs.used_entries().unwrap(), 1);
+
+    drop(fs);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    assert_eq!(fs.used_entries().unwrap(), 1);
+}
+
+#[test]
+fn allocation_is_next_fit() {
+    let mut device = common::format_image(70);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    let first: Vec<_> = (0..3).map(|_| fs.alloc_chunk().unwrap()).collect();
+    assert_eq!(first, vec![2, 3, 4]);
+
+    fs.write_fat_entry(2, FRE).unwrap();
+    assert_eq!(fs.alloc_chunk().unwrap(), 5);
+
+    while fs.free_clusters().unwrap() > 1 {
+        fs.alloc_chunk().unwrap();
+    }
+
+    assert_eq!(fs.alloc_chunk().unwrap(), 2);
+    assert!(matches!(fs.alloc_chunk(), Err(FsError::NoSpace)));
+}
+
+#[test]
+fn cluster_size_is_read_from_the_header() {
+    for cluster_size in [512, 4096, 65536] {
+        let mut device = common::format_image_with(8, cluster_size);
+        let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+        assert_eq!(fs.io.cluster_size(), cluster_size);
+        assert_eq!(fs.io.entries_per_cluster(), cluster_size / DIR_ENTRY_SIZE);
+
+        let (c, i) = fs.create_file(1, 0, "file", 0, 0, 0o644).unwrap();
+        let mut entry = fs.read_directory_entry(c, i).unwrap();
+        let content: Vec<u8> = (0..cluster_size * 2 + 7).map(|i| i as u8).collect();
+
+        if let DirectoryEntry::File(inode) = &mut entry {
+            fs.write_data(inode, 0, &content).unwrap();
+            assert_eq!(fs.get_chain(inode.start_cluster).unwrap().len(), 3);
+            assert_eq!(fs.read_data(inode, 0, u32::MAX).unwrap(), content);
+        }
+
+        let inode = fs.inodes.inode(c, i);
+        assert_eq!(fs.inodes.resolve(inode), (c, i));
+    }
+
+    let mut device = common::format_image(4);
+    let header = device.get_mut();
+
+    header[14..18].copy_from_slice(&1000u32.to_le_bytes());
+    assert!(matches!(
+        FileSystemBasicIO::open_file_system(&mut device),
+        Err(FsError::BadClusterSize(1000))
+    ));
+
+    let mut legacy = common::format_image_with(4, 1024);
+    legacy.get_mut()[9] = 1;
+    legacy.get_mut()[14..18].copy_from_slice(&[0xAA; 4]);
+
+    let io = FileSystemBasicIO::open_file_system(&mut legacy).unwrap();
+    assert_eq!(io.cluster_size, 1024);
+}
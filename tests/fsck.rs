@@ -0,0 +1,135 @@
+mod common;
+
+use naths_fat_fs::{
+    consts::EOC,
+    fs::{
+        basic_fs_io::{BaseIO, FileSystemBasicIO},
+        directory::DirectoryEntry,
+        FileSystem,
+    },
+    fsck::{check, Problem},
+};
+
+#[test]
+fn fresh_image_is_clean() {
+    let mut device = common::format_image(16);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    let (c, i) = fs.create_dir(1, 0, "dir", 0, 0, 0o755).unwrap();
+    let (fc, fi) = fs.create_file(c, i, "file", 0, 0, 0o644).unwrap();
+
+    let mut entry = fs.read_directory_entry(fc, fi).unwrap();
+    if let DirectoryEntry::File(inode) = &mut entry {
+        fs.write_data(inode, 0, &vec![1; cluster_size as usize * 2 + 1])
+            .unwrap();
+    }
+    fs.write_directory_entry(fc, fi, &entry).unwrap();
+
+    assert_eq!(check(&mut fs, false).unwrap(), vec![]);
+}
+
+#[test]
+fn broken_chains_are_reported_and_repaired() {
+    let mut device = common::format_image(32);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    let (bc, bi) = fs.create_file(1, 0, "bad_link", 0, 0, 0o644).unwrap();
+    let (lc, li) = fs.create_file(1, 0, "loop", 0, 0, 0o644).unwrap();
+    let (xc, xi) = fs.create_file(1, 0, "cross", 0, 0, 0o644).unwrap();
+    let (sc, si) = fs.create_file(1, 0, "short", 0, 0, 0o644).unwrap();
+
+    let bad_link = common::start_cluster(&mut fs, bc, bi);
+    let looping = common::start_cluster(&mut fs, lc, li);
+    let cross = common::start_cluster(&mut fs, xc, xi);
+
+    fs.write_fat_entry(bad_link, 0x1234).unwrap();
+    fs.write_fat_entry(looping, looping).unwrap();
+    fs.write_fat_entry(cross, bad_link).unwrap();
+
+    let mut entry = fs.read_directory_entry(sc, si).unwrap();
+    if let DirectoryEntry::File(inode) = &mut entry {
+        inode.length = cluster_size as u64 * 3;
+    }
+    fs.write_directory_entry(sc, si, &entry).unwrap();
+
+    let problems = check(&mut fs, false).unwrap();
+
+    assert!(problems.contains(&Problem::BadLink {
+        path: "/bad_link".to_string(),
+        cluster: bad_link,
+        next: 0x1234
+    }));
+    assert!(problems.contains(&Problem::Loop {
+        path: "/loop".to_string(),
+        cluster: looping
+    }));
+    assert!(problems.contains(&Problem::CrossLinked {
+        path: "/cross".to_string(),
+        cluster: bad_link,
+        owner: "/bad_link".to_string()
+    }));
+    assert!(problems.contains(&Problem::LengthMismatch {
+        path: "/short".to_string(),
+        length: cluster_size as u64 * 3,
+        clusters: 1
+    }));
+
+    check(&mut fs, true).unwrap();
+    assert_eq!(check(&mut fs, false).unwrap(), vec![]);
+
+    assert_eq!(fs.read_fat_entry(bad_link).unwrap(), EOC);
+    assert_eq!(fs.read_fat_entry(looping).unwrap(), EOC);
+    assert_eq!(fs.read_fat_entry(cross).unwrap(), EOC);
+}
+
+#[test]
+fn orphans_are_moved_to_lost_and_found() {
+    let mut device = common::format_image(32);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let cluster_size = fs.io.cluster_size();
+
+    let mut orphan = vec![];
+    fs.append_to_chain(&mut orphan).unwrap();
+    fs.append_to_chain(&mut orphan).unwrap();
+
+    let (c, i) = fs.create_dir(1, 0, "dir", 0, 0, 0o755).unwrap();
+    let dir = common::start_cluster(&mut fs, c, i);
+    fs.write_directory_entry(dir, 1, &DirectoryEntry::Invalid)
+        .unwrap();
+
+    let problems = check(&mut fs, false).unwrap();
+
+    assert_eq!(
+        problems,
+        vec![
+            Problem::BrokenDotEntry {
+                path: "/dir".to_string(),
+                name: ".."
+            },
+            Problem::Orphaned {
+                cluster: orphan[0],
+                clusters: 2
+            }
+        ]
+    );
+
+    check(&mut fs, true).unwrap();
+    assert_eq!(check(&mut fs, false).unwrap(), vec![]);
+
+    let root = fs.get_chain(1).unwrap();
+    let (_, c, i) = fs.find_in_dir(&root, "lost+found").unwrap().unwrap();
+    let lost_found = fs.dir_chain(c, i).unwrap();
+
+    match fs
+        .find_in_dir(&lost_found, &format!("#{}", orphan[0]))
+        .unwrap()
+    {
+        Some((DirectoryEntry::File(inode), _, _)) => {
+            assert_eq!(inode.start_cluster, orphan[0]);
+            assert_eq!(inode.length, cluster_size as u64 * 2);
+        }
+        e => panic!("unexpected entry {:?}", e),
+    }
+}
@@ -0,0 +1,374 @@
+mod common;
+
+use std::{
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use naths_fat_fs::{
+    consts::{DEFAULT_CLUSTER_SIZE, DIR_ENTRY_SIZE, EOC, FRE},
+    error::FsError,
+    fs::{
+        basic_fs_io::{BaseIO, Device, FileSystemBasicIO},
+        directory::{DirectoryEntry, Inode},
+        journal::{self, Transaction},
+        volume::Volume,
+        walk::WalkOrder,
+        FileSystem,
+    },
+    fsck::{check, Problem},
+    mkfs::create_journal,
+    utility::fs_utility::get_data_section_address,
+    DirEntry,
+};
+
+fn journaled_image() -> Cursor<Vec<u8>> {
+    let mut device = common::format_image(32);
+
+    create_journal(4, &mut device).unwrap();
+
+    device
+}
+
+fn new_file() -> DirEntry {
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+
+    DirEntry::from(&DirectoryEntry::File(Inode::new(
+        String::from("f"),
+        0,
+        0,
+        0,
+        0o644,
+        now,
+        now,
+        now,
+        1,
+        5,
+    )))
+}
+
+fn log_new_file(device: &mut Cursor<Vec<u8>>) -> (u32, u32) {
+    let mut io = FileSystemBasicIO::open_file_system(device).unwrap();
+    let (start, length) = io.journal().unwrap();
+
+    let mut transaction = Transaction::default();
+    transaction.fat.insert(5, EOC);
+    transaction.entries.insert((1, 3), new_file());
+
+    assert!(journal::log(&mut io, start, length, &transaction).unwrap());
+
+    (start, length)
+}
+
+#[test]
+fn logged_transaction_is_replayed_on_open() {
+    let mut device = journaled_image();
+    let (start, length) = log_new_file(&mut device);
+
+    let mut io = FileSystemBasicIO::open_file_system(&mut device).unwrap();
+
+    assert_eq!(io.read_fat_entry(5).unwrap(), EOC);
+    assert_eq!(io.read_raw_directory_entry(1, 3).unwrap(), new_file());
+    assert!(!journal::replay(&mut io, start, length).unwrap());
+
+    let mut fs = FileSystem::new(io);
+    assert_eq!(check(&mut fs, false).unwrap(), vec![]);
+}
+
+#[test]
+fn torn_transaction_is_discarded() {
+    let mut device = journaled_image();
+    let (start, _) = log_new_file(&mut device);
+
+    // opening would replay it, so break the payload on the raw image
+    let payload = get_data_section_address(32) + start as u64 * DEFAULT_CLUSTER_SIZE as u64;
+    device.get_mut()[payload as usize + 3] ^= 0xFF;
+
+    let mut io = FileSystemBasicIO::open_file_system(&mut device).unwrap();
+
+    assert_eq!(io.read_fat_entry(5).unwrap(), FRE);
+    assert!(matches!(
+        DirectoryEntry::try_from(&io.read_raw_directory_entry(1, 3).unwrap()).unwrap(),
+        DirectoryEntry::Invalid
+    ));
+}
+
+#[test]
+fn read_only_open_sees_the_transaction_without_replaying_it() {
+    let mut device = journaled_image();
+    let (start, length) = log_new_file(&mut device);
+    let image = device.get_ref().clone();
+
+    let mut io = FileSystemBasicIO::open_read_only(&mut device).unwrap();
+
+    assert_eq!(io.read_fat_entry(5).unwrap(), EOC);
+    assert_eq!(io.read_fat(4, 3).unwrap(), vec![FRE, EOC, FRE]);
+    assert_eq!(io.read_raw_directory_entry(1, 3).unwrap(), new_file());
+    assert_eq!(
+        io.read_cluster(1).unwrap()[3 * DIR_ENTRY_SIZE as usize..][..DIR_ENTRY_SIZE as usize],
+        new_file()
+    );
+
+    let mut fs = FileSystem::new(io);
+    assert_eq!(check(&mut fs, false).unwrap(), vec![]);
+    assert!(journal::pending(&mut fs.io, start, length)
+        .unwrap()
+        .is_some());
+
+    assert_eq!(device.get_ref(), &image);
+}
+
+/// Stops writing after a given number of writes, like a machine losing power.
+/// Only what was synced before is kept.
+struct Crashing {
+    inner: Cursor<Vec<u8>>,
+    writes: usize,
+    durable: Vec<u8>,
+}
+
+impl Crashing {
+    fn new(image: &[u8], writes: usize) -> Self {
+        Crashing {
+            inner: Cursor::new(image.to_vec()),
+            writes,
+            durable: image.to_vec(),
+        }
+    }
+}
+
+impl Read for Crashing {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for Crashing {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.writes == 0 {
+            return Err(io::Error::other("crashed"));
+        }
+
+        self.writes -= 1;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Crashing {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl Device for Crashing {
+    fn sync_data(&mut self) -> io::Result<()> {
+        if self.writes == 0 {
+            return Err(io::Error::other("crashed"));
+        }
+
+        self.durable = self.inner.get_ref().clone();
+
+        Ok(())
+    }
+}
+
+type CrashingImage<'a> = Volume<FileSystemBasicIO<&'a mut Crashing>>;
+
+fn listing(image: &[u8]) -> Vec<(String, u64)> {
+    let io = FileSystemBasicIO::open_file_system(Cursor::new(image.to_vec())).unwrap();
+    let mut fs = FileSystem::new(io);
+
+    assert_eq!(check(&mut fs, false).unwrap(), vec![]);
+
+    fs.walk(WalkOrder::DepthFirst)
+        .map(|result| match result.unwrap() {
+            (path, DirectoryEntry::File(inode), _) => (path, inode.length),
+            (path, _, _) => (path, 0),
+        })
+        .collect()
+}
+
+fn crash_everywhere(image: &[u8], op: impl Fn(&mut CrashingImage) -> io::Result<()>) {
+    let before = listing(image);
+
+    let mut device = Crashing::new(image, usize::MAX);
+    op(&mut Volume::new(
+        FileSystemBasicIO::open_file_system(&mut device).unwrap(),
+    ))
+    .unwrap();
+
+    let done = listing(&device.durable);
+    assert_ne!(done, before);
+
+    for writes in 0.. {
+        let mut device = Crashing::new(image, writes);
+
+        let result = {
+            let mut volume = Volume::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+            op(&mut volume)
+        };
+
+        let after = listing(&device.durable);
+
+        assert!(
+            after == before || after == done,
+            "crash after {} writes left {:?}",
+            writes,
+            after
+        );
+
+        if result.is_ok() {
+            break;
+        }
+    }
+}
+
+#[test]
+fn operations_survive_a_crash_at_any_point() {
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(journaled_image()).unwrap());
+    let cluster_size = volume.fs.io.cluster_size() as usize;
+
+    volume.create_dir("/d").unwrap();
+    volume
+        .create("/a")
+        .unwrap()
+        .write_all(&vec![1; cluster_size * 3])
+        .unwrap();
+
+    let image = volume.into_inner().into_inner().into_inner().into_inner();
+
+    crash_everywhere(&image, |v| v.create("/d/new").map(|_| ()));
+    crash_everywhere(&image, |v| v.create_dir("/d/dir"));
+    crash_everywhere(&image, |v| v.rename("/a", "/d/b"));
+    crash_everywhere(&image, |v| v.remove_file("/a"));
+    crash_everywhere(&image, |v| v.remove_dir("/d"));
+    crash_everywhere(&image, |v| v.open("/a")?.set_len(1));
+}
+
+fn big_file_image() -> Vec<u8> {
+    // the smallest journal only holds 56 FAT records with 512 byte clusters
+    let mut device = common::format_image_with(256, 512);
+    create_journal(2, &mut device).unwrap();
+
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(device).unwrap());
+    volume
+        .create("/big")
+        .unwrap()
+        .write_all(&vec![1; 512 * 100])
+        .unwrap();
+
+    volume.into_inner().into_inner().into_inner().into_inner()
+}
+
+#[test]
+fn frees_too_big_for_the_journal_are_split_into_batches() {
+    let image = big_file_image();
+
+    let free_before = {
+        let io = FileSystemBasicIO::open_file_system(Cursor::new(image.clone())).unwrap();
+        FileSystem::new(io).free_clusters().unwrap()
+    };
+
+    for writes in 0.. {
+        let mut device = Crashing::new(&image, writes);
+
+        let result = {
+            let mut volume = Volume::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+            volume.remove_file("/big")
+        };
+
+        let io = FileSystemBasicIO::open_file_system(Cursor::new(device.durable.clone())).unwrap();
+        let mut fs = FileSystem::new(io);
+        let problems = check(&mut fs, false).unwrap();
+        let files = fs.walk(WalkOrder::DepthFirst).count();
+
+        // a crash between the batches only leaks the clusters not freed yet
+        assert!(
+            (files == 1 && problems.is_empty())
+                || (files == 0
+                    && problems
+                        .iter()
+                        .all(|p| matches!(p, Problem::Orphaned { .. }))),
+            "crash after {} writes left {} files and {:?}",
+            writes,
+            files,
+            problems
+        );
+
+        if result.is_ok() {
+            assert_eq!(files, 0);
+            assert_eq!(problems, vec![]);
+            assert_eq!(fs.free_clusters().unwrap(), free_before + 100);
+            break;
+        }
+    }
+}
+
+#[test]
+fn growing_beyond_the_journal_links_outside_of_it() {
+    let image = big_file_image();
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(Cursor::new(image)).unwrap());
+    let free_before = volume.fs.free_clusters().unwrap();
+
+    let mut file = volume.open("/big").unwrap();
+    file.set_len(512 * 150).unwrap();
+
+    let mut content = vec![];
+    file.read_to_end(&mut content).unwrap();
+    assert_eq!(content[512 * 100 - 1..512 * 100 + 1], [1, 0]);
+    assert_eq!(content.len(), 512 * 150);
+
+    assert_eq!(volume.metadata("/big").unwrap().len, 512 * 150);
+    assert_eq!(volume.fs.free_clusters().unwrap(), free_before - 50);
+    assert_eq!(check(&mut volume.fs, false).unwrap(), vec![]);
+}
+
+#[test]
+fn transaction_without_frees_to_split_off_is_refused() {
+    let mut device = Cursor::new(big_file_image());
+    let image = device.get_ref().clone();
+
+    let mut io = FileSystemBasicIO::open_file_system(&mut device).unwrap();
+    let (start, length) = io.journal().unwrap();
+
+    let mut transaction = Transaction::default();
+    transaction
+        .fat
+        .extend((110..180).map(|cluster| (cluster, EOC)));
+
+    assert!(matches!(
+        journal::commit(&mut io, start, length, &transaction),
+        Err(FsError::JournalFull)
+    ));
+    assert_eq!(device.get_ref(), &image);
+}
+
+#[test]
+fn failed_commit_leaves_inode_numbers_and_counts_alone() {
+    let mut device = Crashing::new(&journaled_image().into_inner(), usize::MAX);
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+
+    let dir = fs.create_dir(1, 0, "d", 0, 0, 0o755).unwrap();
+    let (c, i) = fs.create_file(1, 0, "a", 0, 0, 0o644).unwrap();
+    fs.create_file(dir.0, dir.1, "a", 0, 0, 0o644).unwrap();
+
+    let ino = fs.inodes.inode(c, i);
+    let used = fs.used_entries().unwrap();
+
+    // replacing /d/a moves the inode and drops an entry, but the journal can't be written
+    fs.io.device.writes = 0;
+    assert_eq!(fs.rename(1, 0, "a", dir.0, dir.1, "a", 0), Err(libc::EIO));
+
+    assert_eq!(fs.inodes.resolve(ino), (c, i));
+    assert_eq!(fs.inodes.inode(c, i), ino);
+    assert_eq!(fs.used_entries().unwrap(), used);
+
+    let root = fs.get_chain(1).unwrap();
+    assert!(matches!(
+        fs.find_in_dir(&root, "a").unwrap(),
+        Some((_, fc, fi)) if (fc, fi) == (c, i)
+    ));
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::{
+    fs,
+    io::{Cursor, Read},
+    os::unix::fs::{symlink, MetadataExt, PermissionsExt},
+    time::UNIX_EPOCH,
+};
+
+use naths_fat_fs::{
+    consts::{DATA_REGION, EOC, FAT_PADDING, FS_ID},
+    fs::{basic_fs_io::FileSystemBasicIO, volume::Volume, FileSystem},
+    fsck::check,
+    mkfs::{clusters_for_dir, format, populate, quick_format, write_data_section, write_prelude},
+    utility::fs_utility::{get_data_section_address, get_image_size},
+};
+
+fn reference_image(fat_size: u32, cluster_size: u32) -> Vec<u8> {
+    let mut image = vec![];
+
+    image.extend_from_slice(&FS_ID);
+    image.push(3);
+    image.extend_from_slice(&fat_size.to_le_bytes());
+    image.extend_from_slice(&cluster_size.to_le_bytes());
+    image.extend_from_slice(&[0u8; 8]);
+    image.extend_from_slice(&[FAT_PADDING; 6]);
+    image.extend_from_slice(&vec![0; fat_size as usize * 4]);
+
+    while image.len() as u64 != get_data_section_address(fat_size) {
+        image.push(FAT_PADDING);
+    }
+
+    image.extend_from_slice(&vec![DATA_REGION; (fat_size * cluster_size) as usize]);
+
+    image
+}
+
+#[test]
+fn bulk_writes_produce_the_same_layout() {
+    for (fat_size, cluster_size) in [(8, 512), (3000, 4096), (333, 65536)] {
+        let mut device = Cursor::new(vec![]);
+
+        write_prelude(fat_size, cluster_size, &mut device).unwrap();
+        write_data_section(fat_size, cluster_size, &mut device).unwrap();
+
+        assert_eq!(device.into_inner(), reference_image(fat_size, cluster_size));
+    }
+}
+
+#[test]
+fn quick_format_leaves_the_data_region_alone() {
+    let mut device = Cursor::new(vec![0x77; 100]);
+
+    quick_format(16, 4096, &mut device).unwrap();
+
+    let image = device.get_ref();
+    let data_start = get_data_section_address(16) as usize;
+
+    assert_eq!(image.len() as u64, get_image_size(16, 4096));
+    let mut expected = reference_image(16, 4096);
+    expected[32..36].copy_from_slice(&EOC.to_le_bytes());
+
+    assert_eq!(image[..data_start], expected[..data_start]);
+    assert!(image[data_start + 4096..].iter().all(|b| *b == 0));
+
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    assert_eq!(check(&mut fs, false).unwrap(), vec![]);
+    assert_eq!(fs.free_clusters().unwrap(), 15);
+}
+
+#[test]
+fn populate_copies_a_host_directory() {
+    let host = common::TempDir::new("populate");
+    let long_name = "a file name that needs more than one entry.txt";
+    let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+
+    fs::create_dir_all(host.join("sub/empty")).unwrap();
+    fs::write(host.join("sub").join(long_name), &data).unwrap();
+    fs::write(host.join("empty"), b"").unwrap();
+    fs::set_permissions(host.join("empty"), fs::Permissions::from_mode(0o600)).unwrap();
+    symlink("empty", host.join("link")).unwrap();
+
+    let clusters = clusters_for_dir(&host, 1024).unwrap();
+    let mut device = Cursor::new(vec![]);
+    format(clusters, 1024, &mut device).unwrap();
+
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(&mut device).unwrap());
+    let skipped = populate(&mut volume.fs, &host).unwrap();
+
+    assert_eq!(skipped, vec![host.join("link")]);
+    assert_eq!(volume.fs.free_clusters().unwrap(), 0);
+    assert_eq!(check(&mut volume.fs, false).unwrap(), vec![]);
+
+    let mut content = vec![];
+    volume
+        .open(&format!("/sub/{}", long_name))
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    assert_eq!(content, data);
+
+    let host_meta = fs::metadata(host.join("empty")).unwrap();
+    let meta = volume.metadata("/empty").unwrap();
+
+    assert_eq!(meta.permission, 0o600);
+    assert_eq!(meta.uid, host_meta.uid());
+    assert_eq!(meta.len, 0);
+    assert_eq!(
+        meta.mtime.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        host_meta.mtime() as u64
+    );
+    assert!(volume.metadata("/sub/empty").unwrap().is_dir);
+}
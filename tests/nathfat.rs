@@ -0,0 +1,98 @@
+mod common;
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+    process::Command,
+};
+
+use naths_fat_fs::{
+    consts::DEFAULT_CLUSTER_SIZE,
+    fs::{basic_fs_io::FileSystemBasicIO, volume::Volume},
+    mkfs::format,
+};
+
+fn nathfat(args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_nathfat"))
+        .args(args)
+        .status()
+        .unwrap();
+
+    assert!(status.success(), "nathfat {:?} failed", args);
+}
+
+fn read(volume: &mut Volume<FileSystemBasicIO<File>>, path: &str) -> Vec<u8> {
+    let mut content = vec![];
+    volume
+        .open(path)
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    content
+}
+
+#[test]
+fn copies_and_moves_into_directories() {
+    let tmp = common::TempDir::new("cli-cp");
+    let image = tmp.join("disk.img");
+    let image = image.to_str().unwrap();
+
+    format(
+        32,
+        DEFAULT_CLUSTER_SIZE,
+        &mut File::create_new(image).unwrap(),
+    )
+    .unwrap();
+    fs::write(tmp.join("hello.txt"), b"hello").unwrap();
+
+    let host = tmp.join("hello.txt");
+    let host = host.to_str().unwrap();
+
+    nathfat(&["mkdir", image, "::/dir"]);
+    nathfat(&["cp", image, host, "::/dir"]);
+    nathfat(&["cp", image, host, "::/renamed.txt"]);
+    nathfat(&["cp", image, "::/dir/hello.txt", "::/copy.txt"]);
+    nathfat(&["mv", image, "::/renamed.txt", "::/dir"]);
+    nathfat(&["mv", image, "::/copy.txt", "::/moved.txt"]);
+    nathfat(&["cp", image, "::/moved.txt", tmp.to_str().unwrap()]);
+
+    let mut volume = common::open_image(Path::new(image));
+
+    assert_eq!(read(&mut volume, "/dir/hello.txt"), b"hello");
+    assert_eq!(read(&mut volume, "/dir/renamed.txt"), b"hello");
+    assert_eq!(read(&mut volume, "/moved.txt"), b"hello");
+    assert!(volume.metadata("/renamed.txt").is_err());
+    assert!(volume.metadata("/copy.txt").is_err());
+    assert_eq!(fs::read(tmp.join("moved.txt")).unwrap(), b"hello");
+}
+
+#[test]
+fn mkdir_creates_missing_parents() {
+    let tmp = common::TempDir::new("cli-mkdir");
+    let image = tmp.join("disk.img");
+    let image = image.to_str().unwrap();
+
+    format(
+        32,
+        DEFAULT_CLUSTER_SIZE,
+        &mut File::create_new(image).unwrap(),
+    )
+    .unwrap();
+
+    nathfat(&["mkdir", image, "-p", "::/a/b/c"]);
+    nathfat(&["mkdir", image, "-p", "::/a/b/d"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_nathfat"))
+        .args(["mkdir", image, "::/x/y"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let mut volume = common::open_image(Path::new(image));
+
+    for dir in ["/a", "/a/b", "/a/b/c", "/a/b/d"] {
+        assert!(volume.metadata(dir).unwrap().is_dir, "{} missing", dir);
+    }
+    assert!(volume.metadata("/x").is_err());
+}
@@ -1,5 +1,6 @@
 use fuser::{mount2, MountOption};
 use naths_fat_fs::{
+    consts::DEFAULT_CLUSTER_SIZE,
     fs::{
         basic_fs_io::{BaseIO, FileSystemBasicIO},
         directory::{DirectoryEntry, Inode},
@@ -20,25 +21,23 @@ fn test() {
         .open(Path::new("test.hex"))
         .unwrap();
 
-    write_prelude(16, &mut file);
-    write_data_section(16, &mut file);
+    write_prelude(16, DEFAULT_CLUSTER_SIZE, &mut file).unwrap();
+    write_data_section(16, DEFAULT_CLUSTER_SIZE, &mut file).unwrap();
 
-    let mut fs = FileSystem {
-        io: FileSystemBasicIO::open_file_system(&mut file),
-    };
+    let mut fs = FileSystem::new(FileSystemBasicIO::open_file_system(&mut file).unwrap());
 
-    write_root_dir(&mut fs);
+    write_root_dir(&mut fs).unwrap();
 
     let mut new_file = vec![];
 
-    fs.append_to_chain(&mut new_file);
+    fs.append_to_chain(&mut new_file).unwrap();
 
-    let mut block = fs.read_cluster(new_file[0]);
+    let mut block = fs.read_cluster(new_file[0]).unwrap();
     let cont = b"Das ist eine Hello.txt file :)";
 
     block[0..cont.len()].copy_from_slice(cont);
 
-    fs.write_cluster(new_file[0], &block);
+    fs.write_cluster(new_file[0], &block).unwrap();
 
     fs.write_raw_directory_entry(
         1,
@@ -55,7 +54,8 @@ fn test() {
             1,
             new_file[0],
         ))),
-    );
+    )
+    .unwrap();
 
     mount2(
         fs,
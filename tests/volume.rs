@@ -0,0 +1,117 @@
+mod common;
+
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use naths_fat_fs::fs::{basic_fs_io::FileSystemBasicIO, volume::Volume};
+
+#[test]
+fn files_are_readable_and_seekable() {
+    let device = common::format_image(32);
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(device).unwrap());
+
+    volume.create_dir("/a").unwrap();
+
+    let data: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+
+    let mut file = volume.create("/a/b.txt").unwrap();
+    file.write_all(&data).unwrap();
+    file.seek(SeekFrom::Start(5000)).unwrap();
+    file.write_all(b"hello").unwrap();
+    drop(file);
+
+    let mut file = volume.open("/a/./b.txt").unwrap();
+    let mut content = vec![];
+    file.read_to_end(&mut content).unwrap();
+
+    assert_eq!(content.len(), data.len());
+    assert_eq!(&content[5000..5005], b"hello");
+    assert_eq!(content[..5000], data[..5000]);
+
+    file.seek(SeekFrom::End(-5)).unwrap();
+    let mut tail = [0; 10];
+    assert_eq!(file.read(&mut tail).unwrap(), 5);
+    assert_eq!(tail[..5], data[9995..]);
+    drop(file);
+
+    let mut file = volume.create("/a/b.txt").unwrap();
+    assert!(file.is_empty());
+    file.write_all(b"short").unwrap();
+    drop(file);
+
+    assert_eq!(volume.metadata("/a/../a/b.txt").unwrap().len, 5);
+}
+
+#[test]
+fn permissions_and_owner_can_be_changed() {
+    let device = common::format_image(32);
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(device).unwrap());
+
+    volume.create("/file").unwrap().write_all(b"short").unwrap();
+    volume.create_dir("/dir").unwrap();
+
+    volume.set_permissions("/file", 0o600).unwrap();
+    volume.set_owner("/file", Some(7), None).unwrap();
+    volume.set_owner("/dir", None, Some(9)).unwrap();
+
+    let metadata = volume.metadata("/file").unwrap();
+
+    assert_eq!(metadata.len, 5);
+    assert_eq!(metadata.permission, 0o600);
+    assert_eq!((metadata.uid, metadata.gid), (7, 0));
+
+    let metadata = volume.metadata("/dir").unwrap();
+
+    assert!(metadata.is_dir);
+    assert_eq!((metadata.uid, metadata.gid), (0, 9));
+    assert_eq!(
+        volume
+            .set_permissions("/missing", 0o600)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn directories_can_be_changed_by_path() {
+    let device = common::format_image(32);
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(device).unwrap());
+
+    volume.create_dir("/dir").unwrap();
+    volume.create_dir("/dir/sub").unwrap();
+    volume.create("/dir/file").unwrap();
+
+    let mut names: Vec<_> = volume
+        .read_dir("/dir")
+        .unwrap()
+        .into_iter()
+        .map(|m| (m.name, m.is_dir))
+        .collect();
+    names.sort();
+
+    assert_eq!(
+        names,
+        vec![("file".to_string(), false), ("sub".to_string(), true)]
+    );
+
+    volume.rename("/dir/file", "/dir/sub/moved").unwrap();
+    assert!(!volume.metadata("/dir/sub/moved").unwrap().is_dir);
+    assert_eq!(
+        volume.open("/dir/file").err().unwrap().kind(),
+        ErrorKind::NotFound
+    );
+
+    assert_eq!(
+        volume.remove_dir("/dir/sub").unwrap_err().raw_os_error(),
+        Some(libc::ENOTEMPTY)
+    );
+    assert_eq!(
+        volume.open("/dir").err().unwrap().raw_os_error(),
+        Some(libc::EISDIR)
+    );
+
+    volume.remove_file("/dir/sub/moved").unwrap();
+    volume.remove_dir("/dir/sub").unwrap();
+
+    assert!(volume.read_dir("/dir").unwrap().is_empty());
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use std::io::Cursor;
+
+use naths_fat_fs::{
+    error::FsError,
+    fs::{
+        basic_fs_io::FileSystemBasicIO,
+        directory::DirectoryEntry,
+        volume::Volume,
+        walk::{WalkError, WalkOrder},
+    },
+};
+
+type Image = Volume<FileSystemBasicIO<Cursor<Vec<u8>>>>;
+
+fn tree() -> Image {
+    let device = common::format_image(32);
+    let mut volume = Volume::new(FileSystemBasicIO::open_file_system(device).unwrap());
+
+    for dir in ["/a", "/a/b", "/c"] {
+        volume.create_dir(dir).unwrap();
+    }
+
+    for file in ["/a/b/f", "/a/g", "/c/h", "/i"] {
+        volume.create(file).unwrap();
+    }
+
+    volume
+}
+
+fn paths(volume: &mut Image, order: WalkOrder) -> Vec<String> {
+    volume.fs.walk(order).map(|r| r.unwrap().0).collect()
+}
+
+#[test]
+fn walk_visits_everything_in_order() {
+    let mut volume = tree();
+
+    assert_eq!(
+        paths(&mut volume, WalkOrder::DepthFirst),
+        ["/a", "/a/b", "/a/b/f", "/a/g", "/c", "/c/h", "/i"]
+    );
+    assert_eq!(
+        paths(&mut volume, WalkOrder::BreadthFirst),
+        ["/a", "/c", "/i", "/a/b", "/a/g", "/c/h", "/a/b/f"]
+    );
+
+    let (c, i) = volume.lookup("/a/b/f").unwrap();
+    let (path, entry, ino) = volume
+        .walk("/a", WalkOrder::DepthFirst)
+        .unwrap()
+        .map(Result::unwrap)
+        .find(|(path, _, _)| path == "/a/b/f")
+        .unwrap();
+
+    assert_eq!(path, "/a/b/f");
+    assert!(matches!(entry, DirectoryEntry::File(_)));
+    assert_eq!(ino, volume.fs.inodes.inode(c, i));
+}
+
+#[test]
+fn walk_can_prune_and_detects_loops() {
+    let mut volume = tree();
+
+    let mut walk = volume.fs.walk(WalkOrder::DepthFirst);
+    let mut seen = vec![];
+
+    while let Some((path, _, _)) = walk.next().map(Result::unwrap) {
+        if path == "/a" {
+            walk.prune();
+        }
+
+        seen.push(path);
+    }
+
+    assert_eq!(seen, ["/a", "/c", "/c/h", "/i"]);
+
+    let (c, i) = volume.lookup("/c").unwrap();
+    let mut entry = volume.fs.read_directory_entry(c, i).unwrap();
+    if let DirectoryEntry::Directory(inode) = &mut entry {
+        inode.start_cluster = 1;
+    }
+    volume.fs.write_directory_entry(c, i, &entry).unwrap();
+
+    let results: Vec<_> = volume.fs.walk(WalkOrder::DepthFirst).collect();
+
+    assert_eq!(results.len(), 7);
+    assert!(matches!(
+        &results[5],
+        Err(WalkError {
+            path,
+            error: FsError::DirectoryLoop(1)
+        }) if path == "/c"
+    ));
+}